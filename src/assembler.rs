@@ -0,0 +1,624 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::opcodes::{self, OpInfo, Operand};
+
+/// An assembly error tied to a source line (1-based)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Assembled program: a contiguous block of bytes plus the symbol table
+#[derive(Debug, Clone, Default)]
+pub struct Image {
+    pub origin: u16,                      // Address of the first byte
+    pub bytes: Vec<u8>,                   // Gaps between `.org` blocks are zero-filled
+    pub symbols: BTreeMap<String, u16>,   // Labels and constants
+}
+
+impl Image {
+    /// Render the symbol table as `NAME = $XXXX` lines, which the assembler also accepts
+    pub fn symbol_listing(&self) -> String {
+        self.symbols
+            .iter()
+            .map(|(name, value)| format!("{} = ${:04X}\n", name, value))
+            .collect()
+    }
+}
+
+/// Parse a symbol listing produced by `Image::symbol_listing`
+pub fn parse_symbols(text: &str) -> Result<BTreeMap<String, u16>, AsmError> {
+    let image = assemble(text)?;
+    Ok(image.symbols)
+}
+
+/// One item of a `.byte` list
+#[derive(Debug)]
+enum ByteItem {
+    Expr(String),
+    Text(Vec<u8>),
+}
+
+#[derive(Debug)]
+enum Statement {
+    Instruction(&'static OpInfo, Option<String>),
+    Bytes(Vec<ByteItem>),
+    Words(Vec<String>),
+}
+
+/// A statement placed at its address during the first pass
+#[derive(Debug)]
+struct Placed {
+    line: usize,
+    address: u16,
+    statement: Statement,
+}
+
+/// Why an expression could not be evaluated
+enum EvalError {
+    Undefined(String), // May resolve once more symbols are known
+    Invalid(String),
+}
+
+struct Assembler {
+    symbols: HashMap<String, i64>,
+    constants: Vec<(usize, String, String, i64)>, // Forward-referencing constants: (line, name, expr, pc there)
+    placed: Vec<Placed>,
+    pc: u32, // 32-bit so running off the end of the address space is detectable
+}
+
+/// Assemble MBOS source text into a binary image.
+///
+/// Syntax, one statement per line (`;` starts a comment):
+/// - `label:` defines a label at the current address; an instruction may follow it.
+/// - `NAME = expr` or `NAME .equ expr` defines a constant.
+/// - `.org expr` moves the current address; `.byte`, `.word` and `.string`
+///   emit data (`.string` appends a NUL, `.byte` accepts unterminated strings).
+/// - Instructions use the mnemonics from `opcodes::OPCODES`. LDA takes a one byte
+///   immediate (an optional `#` prefix is accepted), jumps and memory operands
+///   take a 16-bit address.
+///
+/// Expressions are sums and differences of numbers (`42`, `$2A`, `0x2A`, `%101010`,
+/// `'*'`), symbols and `*` (the address of the current statement), optionally
+/// prefixed by `<` or `>` to take the low or high byte.
+pub fn assemble(source: &str) -> Result<Image, AsmError> {
+    let mut asm = Assembler {
+        symbols: HashMap::new(),
+        constants: Vec::new(),
+        placed: Vec::new(),
+        pc: 0,
+    };
+    for (index, text) in source.lines().enumerate() {
+        asm.first_pass_line(index + 1, text)?;
+    }
+    asm.resolve_constants()?;
+    asm.second_pass()
+}
+
+impl Assembler {
+    fn first_pass_line(&mut self, line: usize, text: &str) -> Result<(), AsmError> {
+        let mut rest = strip_comment(text).trim();
+        if rest.is_empty() {
+            return Ok(());
+        }
+
+        // Leading label
+        if let Some(colon) = rest.find(':') {
+            let name = rest[..colon].trim();
+            if is_symbol(name) {
+                self.define(line, name, self.pc as i64)?;
+                rest = rest[colon + 1..].trim();
+                if rest.is_empty() {
+                    return Ok(());
+                }
+            }
+        }
+
+        let (head, tail) = split_word(rest);
+
+        // Constant definitions: NAME = expr / NAME .equ expr
+        let (second, value) = split_word(tail);
+        if is_symbol(head) && (tail.starts_with('=') || second.eq_ignore_ascii_case(".equ")) {
+            let expr = if let Some(expr) = tail.strip_prefix('=') { expr.trim() } else { value };
+            return self.define_constant(line, head, expr, self.pc as i64);
+        }
+
+        if head.starts_with('.') {
+            return self.directive(line, head, tail);
+        }
+
+        let info = opcodes::find(head)
+            .ok_or_else(|| error(line, format!("unknown instruction '{}'", head)))?;
+        let operand = match (info.operand, tail.is_empty()) {
+            (Operand::None, true) => None,
+            (Operand::None, false) => {
+                return Err(error(line, format!("{} takes no operand", info.mnemonic)));
+            }
+            (_, true) => return Err(error(line, format!("{} needs an operand", info.mnemonic))),
+            (Operand::Immediate, false) => Some(tail.strip_prefix('#').unwrap_or(tail).to_string()),
+            (Operand::Address, false) => Some(tail.to_string()),
+        };
+        self.place(line, Statement::Instruction(info, operand), info.size())
+    }
+
+    fn directive(&mut self, line: usize, name: &str, args: &str) -> Result<(), AsmError> {
+        match name.to_ascii_lowercase().as_str() {
+            ".org" => {
+                let address = self.eval_now(line, args)?;
+                self.pc = check_range(line, address, 0, 0xFFFF)? as u32;
+                Ok(())
+            }
+            ".byte" | ".db" => {
+                let mut items = Vec::new();
+                let mut size = 0;
+                for arg in split_args(line, args)? {
+                    if arg.starts_with('"') {
+                        let text = parse_string(line, arg)?;
+                        size += text.len();
+                        items.push(ByteItem::Text(text));
+                    } else {
+                        size += 1;
+                        items.push(ByteItem::Expr(arg.to_string()));
+                    }
+                }
+                self.place(line, Statement::Bytes(items), size)
+            }
+            ".word" | ".dw" => {
+                let words: Vec<String> = split_args(line, args)?.into_iter().map(String::from).collect();
+                let size = words.len() * 2;
+                self.place(line, Statement::Words(words), size)
+            }
+            ".string" => {
+                let mut text = parse_string(line, args)?;
+                text.push(0);
+                let size = text.len();
+                self.place(line, Statement::Bytes(vec![ByteItem::Text(text)]), size)
+            }
+            _ => Err(error(line, format!("unknown directive '{}'", name))),
+        }
+    }
+
+    fn place(&mut self, line: usize, statement: Statement, size: usize) -> Result<(), AsmError> {
+        if self.pc + size as u32 > 0x10000 {
+            return Err(error(line, "code runs past the end of the address space".to_string()));
+        }
+        self.placed.push(Placed {
+            line,
+            address: self.pc as u16,
+            statement,
+        });
+        self.pc += size as u32;
+        Ok(())
+    }
+
+    fn define(&mut self, line: usize, name: &str, value: i64) -> Result<(), AsmError> {
+        if opcodes::find(name).is_some() {
+            return Err(error(line, format!("'{}' is an instruction name", name)));
+        }
+        if !(-0x8000..=0xFFFF).contains(&value) {
+            return Err(error(line, format!("value {} of '{}' does not fit in 16 bits", value, name)));
+        }
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return Err(error(line, format!("symbol '{}' is already defined", name)));
+        }
+        Ok(())
+    }
+
+    /// Define `name` as `expr`, where `*` is `here`, deferring it if it refers to a later symbol
+    fn define_constant(&mut self, line: usize, name: &str, expr: &str, here: i64) -> Result<(), AsmError> {
+        match self.eval(expr, here) {
+            Ok(value) => self.define(line, name, value),
+            Err(EvalError::Undefined(_)) => {
+                self.constants.push((line, name.to_string(), expr.to_string(), here));
+                Ok(())
+            }
+            Err(EvalError::Invalid(message)) => Err(error(line, message)),
+        }
+    }
+
+    /// Resolve constants that referred to symbols defined later in the source
+    fn resolve_constants(&mut self) -> Result<(), AsmError> {
+        while !self.constants.is_empty() {
+            let before = self.constants.len();
+            for (line, name, expr, here) in std::mem::take(&mut self.constants) {
+                self.define_constant(line, &name, &expr, here)?;
+            }
+            if self.constants.len() == before {
+                let (line, _, expr, here) = &self.constants[0];
+                return Err(match self.eval(expr, *here) {
+                    Err(EvalError::Undefined(symbol)) => {
+                        error(*line, format!("undefined symbol '{}'", symbol))
+                    }
+                    _ => error(*line, "circular constant definition".to_string()),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluate an expression that must not contain forward references (e.g. `.org`)
+    fn eval_now(&self, line: usize, expr: &str) -> Result<i64, AsmError> {
+        self.eval(expr, self.pc as i64).map_err(|err| match err {
+            EvalError::Undefined(symbol) => {
+                error(line, format!("symbol '{}' must be defined before use here", symbol))
+            }
+            EvalError::Invalid(message) => error(line, message),
+        })
+    }
+
+    fn eval_final(&self, line: usize, expr: &str, here: u16) -> Result<i64, AsmError> {
+        self.eval(expr, here as i64).map_err(|err| match err {
+            EvalError::Undefined(symbol) => error(line, format!("undefined symbol '{}'", symbol)),
+            EvalError::Invalid(message) => error(line, message),
+        })
+    }
+
+    fn second_pass(self) -> Result<Image, AsmError> {
+        let mut output: BTreeMap<u16, (u8, usize)> = BTreeMap::new(); // address -> (byte, line)
+        for placed in &self.placed {
+            let line = placed.line;
+            let mut bytes = Vec::new();
+            match &placed.statement {
+                Statement::Instruction(info, operand) => {
+                    bytes.push(info.opcode);
+                    if let Some(expr) = operand {
+                        let value = self.eval_final(line, expr, placed.address)?;
+                        match info.operand {
+                            Operand::Immediate => bytes.push(check_range(line, value, -128, 0xFF)? as u8),
+                            Operand::Address => {
+                                let address = check_range(line, value, 0, 0xFFFF)? as u16;
+                                bytes.extend_from_slice(&address.to_le_bytes());
+                            }
+                            Operand::None => {}
+                        }
+                    }
+                }
+                Statement::Bytes(items) => {
+                    for item in items {
+                        match item {
+                            ByteItem::Expr(expr) => {
+                                let value = self.eval_final(line, expr, placed.address)?;
+                                bytes.push(check_range(line, value, -128, 0xFF)? as u8);
+                            }
+                            ByteItem::Text(text) => bytes.extend_from_slice(text),
+                        }
+                    }
+                }
+                Statement::Words(words) => {
+                    for expr in words {
+                        let value = self.eval_final(line, expr, placed.address)?;
+                        let word = check_range(line, value, -0x8000, 0xFFFF)? as u16;
+                        bytes.extend_from_slice(&word.to_le_bytes());
+                    }
+                }
+            }
+            for (offset, byte) in bytes.into_iter().enumerate() {
+                let address = placed.address + offset as u16;
+                if let Some((_, first)) = output.insert(address, (byte, line)) {
+                    return Err(error(
+                        line,
+                        format!("address ${:04X} was already assembled at line {}", address, first),
+                    ));
+                }
+            }
+        }
+
+        let mut image = Image {
+            symbols: self
+                .symbols
+                .iter()
+                .map(|(name, value)| (name.clone(), *value as u16)) // `define` keeps values in -$8000..=$FFFF
+                .collect(),
+            ..Image::default()
+        };
+        if let (Some((&first, _)), Some((&last, _))) = (output.first_key_value(), output.last_key_value()) {
+            image.origin = first;
+            image.bytes = vec![0; (last - first) as usize + 1];
+            for (address, (byte, _)) in output {
+                image.bytes[(address - first) as usize] = byte;
+            }
+        }
+        Ok(image)
+    }
+
+    fn eval(&self, expr: &str, here: i64) -> Result<i64, EvalError> {
+        let expr = expr.trim();
+        if expr.is_empty() {
+            return Err(EvalError::Invalid("missing expression".to_string()));
+        }
+        if let Some(inner) = expr.strip_prefix('<') {
+            return Ok(self.eval(inner, here)? & 0xFF);
+        }
+        if let Some(inner) = expr.strip_prefix('>') {
+            return Ok((self.eval(inner, here)? >> 8) & 0xFF);
+        }
+
+        let overflow = || EvalError::Invalid(format!("overflow in '{}'", expr));
+        let add = |total: i64, sign: i64, value: i64| {
+            value.checked_mul(sign).and_then(|value| total.checked_add(value)).ok_or_else(overflow)
+        };
+        let mut total = 0i64;
+        let mut sign = 1i64;
+        let mut expect_term = true;
+        let mut chars = expr.char_indices().peekable();
+        while let Some(&(start, c)) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+                continue;
+            }
+            if expect_term {
+                if c == '-' || c == '+' {
+                    if c == '-' {
+                        sign = -sign;
+                    }
+                    chars.next();
+                    continue;
+                }
+                let mut end = start;
+                if c == '\'' {
+                    // Character literal, e.g. 'A'
+                    let literal: String = expr[start..].chars().take(3).collect();
+                    let mut inner = literal.chars().skip(1);
+                    match (inner.next(), inner.next()) {
+                        (Some(ch), Some('\'')) if ch.is_ascii() => total = add(total, sign, ch as i64)?,
+                        _ => return Err(EvalError::Invalid(format!("bad character literal in '{}'", expr))),
+                    }
+                    for _ in 0..3 {
+                        chars.next();
+                    }
+                } else {
+                    while let Some(&(index, ch)) = chars.peek() {
+                        if ch == '+' || ch == '-' || ch.is_whitespace() {
+                            break;
+                        }
+                        end = index + ch.len_utf8();
+                        chars.next();
+                    }
+                    total = add(total, sign, self.term(&expr[start..end], here)?)?;
+                }
+                sign = 1;
+                expect_term = false;
+            } else {
+                match c {
+                    '+' => sign = 1,
+                    '-' => sign = -1,
+                    _ => return Err(EvalError::Invalid(format!("unexpected '{}' in '{}'", c, expr))),
+                }
+                chars.next();
+                expect_term = true;
+            }
+        }
+        if expect_term {
+            return Err(EvalError::Invalid(format!("incomplete expression '{}'", expr)));
+        }
+        Ok(total)
+    }
+
+    fn term(&self, term: &str, here: i64) -> Result<i64, EvalError> {
+        if term == "*" {
+            return Ok(here);
+        }
+        if let Some(value) = parse_number(term) {
+            return Ok(value);
+        }
+        if is_symbol(term) {
+            return self
+                .symbols
+                .get(term)
+                .copied()
+                .ok_or_else(|| EvalError::Undefined(term.to_string()));
+        }
+        Err(EvalError::Invalid(format!("bad value '{}'", term)))
+    }
+}
+
+fn error(line: usize, message: String) -> AsmError {
+    AsmError { line, message }
+}
+
+fn check_range(line: usize, value: i64, min: i64, max: i64) -> Result<i64, AsmError> {
+    if value < min || value > max {
+        Err(error(line, format!("value {} out of range {}..={}", value, min, max)))
+    } else {
+        Ok(value & 0xFFFF)
+    }
+}
+
+/// Parse a numeric literal: decimal, `$hex`, `0xhex`, `%binary` or `0bbinary`
+pub fn parse_number(text: &str) -> Option<i64> {
+    let (digits, radix) = if let Some(hex) = text.strip_prefix('$') {
+        (hex, 16)
+    } else if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        (hex, 16)
+    } else if let Some(bin) = text.strip_prefix('%') {
+        (bin, 2)
+    } else if let Some(bin) = text.strip_prefix("0b").or_else(|| text.strip_prefix("0B")) {
+        (bin, 2)
+    } else {
+        (text, 10)
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    i64::from_str_radix(digits, radix).ok()
+}
+
+/// Parse a number that is hex unless it has a `$`, `0x`/`0X` or `%` prefix,
+/// as the monitor and memory map files expect
+pub fn parse_hex_default(text: &str) -> Option<i64> {
+    if text.starts_with(['$', '%']) || text.starts_with("0x") || text.starts_with("0X") {
+        parse_number(text)
+    } else {
+        i64::from_str_radix(text, 16).ok()
+    }
+}
+
+fn is_symbol(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Split off the first whitespace-delimited word
+fn split_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(index) => (&text[..index], text[index..].trim()),
+        None => (text, ""),
+    }
+}
+
+/// Remove a `;` comment, ignoring semicolons inside string and character literals
+fn strip_comment(text: &str) -> &str {
+    let mut in_string = false;
+    let mut in_char = false;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if in_string => escaped = true,
+            '"' if !in_char => in_string = !in_string,
+            '\'' if !in_string => in_char = !in_char,
+            ';' if !in_string && !in_char => return &text[..index],
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Split a comma-separated argument list, keeping commas inside strings
+fn split_args(line: usize, text: &str) -> Result<Vec<&str>, AsmError> {
+    let mut args = Vec::new();
+    let mut start = 0;
+    let mut in_string = false;
+    let mut in_char = false;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if in_string => escaped = true,
+            '"' if !in_char => in_string = !in_string,
+            '\'' if !in_string => in_char = !in_char,
+            ',' if !in_string && !in_char => {
+                args.push(text[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    args.push(text[start..].trim());
+    if args.iter().any(|arg| arg.is_empty()) {
+        return Err(error(line, "empty argument".to_string()));
+    }
+    Ok(args)
+}
+
+/// Parse a double-quoted string literal with `\n`, `\r`, `\t`, `\0`, `\\` and `\"` escapes
+fn parse_string(line: usize, text: &str) -> Result<Vec<u8>, AsmError> {
+    let inner = text
+        .trim()
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .ok_or_else(|| error(line, format!("expected a quoted string, found '{}'", text)))?;
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = if c == '\\' {
+            match chars.next() {
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some('\\') => '\\',
+                Some('"') => '"',
+                other => return Err(error(line, format!("bad escape '\\{}'", other.unwrap_or(' ')))),
+            }
+        } else {
+            c
+        };
+        if !c.is_ascii() {
+            return Err(error(line, format!("non-ASCII character '{}' in string", c)));
+        }
+        bytes.push(c as u8);
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(source: &str) -> Vec<u8> {
+        assemble(source).unwrap().bytes
+    }
+
+    fn error_line(source: &str) -> (usize, String) {
+        let err = assemble(source).unwrap_err();
+        (err.line, err.message)
+    }
+
+    #[test]
+    fn evaluates_sums_literals_and_byte_selectors() {
+        assert_eq!(bytes(".byte 2 + 3 - 1, 'A' + 1, -1, %101, 0x10"), [4, b'B', 0xFF, 5, 0x10]);
+        assert_eq!(bytes(".byte <$1234, >$1234"), [0x34, 0x12]);
+        assert_eq!(bytes(".word - -2, $FFFF"), [2, 0, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn star_is_the_address_of_the_statement() {
+        assert_eq!(bytes(".org $100\n.word *, * + 2"), [0x00, 0x01, 0x02, 0x01]);
+    }
+
+    #[test]
+    fn resolves_forward_references() {
+        let image = assemble(".org $200\n        JMP later\nlater:  HALT").unwrap();
+        assert_eq!(image.bytes, [opcodes::JMP, 0x03, 0x02, opcodes::HALT]);
+        assert_eq!(image.symbols["later"], 0x203);
+    }
+
+    #[test]
+    fn deferred_constants_use_the_pc_of_their_definition() {
+        assert_eq!(bytes("x = y + *\ny = 1\n        JMP x"), [opcodes::JMP, 0x01, 0x00]);
+        let source = ".org $10\nx = y + *\n.org $40\ny = 2\n        JMP x";
+        assert_eq!(bytes(source), [opcodes::JMP, 0x12, 0x00]);
+    }
+
+    #[test]
+    fn chained_constants_resolve_in_any_order() {
+        assert_eq!(bytes("a = b + 1\nb = c + 1\nc = 1\n.byte a"), [3]);
+    }
+
+    #[test]
+    fn reports_undefined_and_circular_constants() {
+        assert_eq!(error_line("x = nowhere\n"), (1, "undefined symbol 'nowhere'".to_string()));
+        assert_eq!(error_line("a = b\nb = a\n"), (1, "undefined symbol 'b'".to_string()));
+    }
+
+    #[test]
+    fn reports_overflow_instead_of_panicking() {
+        assert_eq!(error_line("X = $7FFFFFFFFFFFFFFF + 1").1, "overflow in '$7FFFFFFFFFFFFFFF + 1'");
+        assert_eq!(error_line("X = -$7FFFFFFFFFFFFFFF - 2").1, "overflow in '-$7FFFFFFFFFFFFFFF - 2'");
+    }
+
+    #[test]
+    fn rejects_symbols_outside_16_bits() {
+        assert_eq!(error_line("\nX = $10000").0, 2);
+        assert!(assemble("X = -$8001").is_err());
+        assert_eq!(assemble("X = -$8000").unwrap().symbols["X"], 0x8000);
+    }
+}
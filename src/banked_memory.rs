@@ -0,0 +1,184 @@
+use crate::bus::Bus;
+use crate::error::{AccessKind, EmuError};
+
+/// I/O port the MicroBee 128K and 256TC use for their bank latch
+pub const MICROBEE_BANK_PORT: u8 = 0x50;
+
+/// Size of each switchable window in the CPU's 64K address space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowSize {
+    K16, // Four windows, bank select value: bits 7-6 window, bits 5-0 bank
+    K32, // Two windows, bank select value: bit 7 window, bits 6-0 bank
+}
+
+impl WindowSize {
+    /// Window size in bytes
+    pub fn bytes(self) -> usize {
+        match self {
+            WindowSize::K16 => 0x4000,
+            WindowSize::K32 => 0x8000,
+        }
+    }
+
+    /// Number of windows covering the 64K address space
+    pub fn windows(self) -> usize {
+        0x10000 / self.bytes()
+    }
+
+    /// Split a bank select value into (window, bank)
+    fn decode(self, value: u8) -> (usize, usize) {
+        match self {
+            WindowSize::K16 => ((value >> 6) as usize, (value & 0x3F) as usize),
+            WindowSize::K32 => ((value >> 7) as usize, (value & 0x7F) as usize),
+        }
+    }
+}
+
+/// Where one CPU window currently points, as reported to the debugger
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BankMapping {
+    pub window: usize,         // Window index, 0 at the bottom of the address space
+    pub cpu_start: u16,        // First CPU address of the window
+    pub cpu_end: u16,          // Last CPU address of the window
+    pub bank: usize,           // Physical bank mapped into the window
+    pub physical_start: usize, // Physical address of the window's first byte
+}
+
+/// Physical store larger than 64K, mapped into the CPU address space in
+/// 16K or 32K windows that are switched by writing to an I/O port.
+///
+/// At reset window N maps bank N, so the first 64K behave like flat memory.
+pub struct BankedMemory {
+    physical: Vec<u8>,
+    window_size: WindowSize,
+    banks: Vec<usize>, // Bank currently mapped into each window
+    pub port: u8,      // Port whose writes select banks
+}
+
+impl BankedMemory {
+    /// Create `physical_size` bytes of zeroed memory switched in `window_size` windows.
+    ///
+    /// The size must be a multiple of the window size and hold at least 64K.
+    pub fn new(physical_size: usize, window_size: WindowSize) -> Result<Self, String> {
+        let bank_bytes = window_size.bytes();
+        if physical_size < 0x10000 || !physical_size.is_multiple_of(bank_bytes) {
+            return Err(format!(
+                "banked memory must be at least 64K and a multiple of {}K, got {}",
+                bank_bytes / 1024,
+                physical_size
+            ));
+        }
+        let bank_count = physical_size / bank_bytes;
+        let max_bank = match window_size {
+            WindowSize::K16 => 0x40,
+            WindowSize::K32 => 0x80,
+        };
+        if bank_count > max_bank {
+            return Err(format!(
+                "{} banks of {}K cannot all be selected, the limit is {}",
+                bank_count,
+                bank_bytes / 1024,
+                max_bank
+            ));
+        }
+        Ok(BankedMemory {
+            physical: vec![0; physical_size],
+            window_size,
+            banks: (0..window_size.windows()).collect(),
+            port: MICROBEE_BANK_PORT,
+        })
+    }
+
+    /// Number of physical banks
+    pub fn bank_count(&self) -> usize {
+        self.physical.len() / self.window_size.bytes()
+    }
+
+    /// Size of the switchable windows
+    pub fn window_size(&self) -> WindowSize {
+        self.window_size
+    }
+
+    /// The whole physical store
+    pub fn physical(&self) -> &[u8] {
+        &self.physical
+    }
+
+    /// Map `bank` into `window`
+    pub fn select(&mut self, window: usize, bank: usize) -> Result<(), String> {
+        if window >= self.banks.len() {
+            return Err(format!("no window {}", window));
+        }
+        if bank >= self.bank_count() {
+            return Err(format!("no bank {}, there are {}", bank, self.bank_count()));
+        }
+        self.banks[window] = bank;
+        Ok(())
+    }
+
+    /// Handle an I/O port write; returns false if the port is not the bank latch.
+    ///
+    /// Selecting a bank that does not exist wraps modulo the bank count, as the
+    /// real latch ignores the missing address lines.
+    pub fn write_port(&mut self, port: u8, value: u8) -> bool {
+        if port != self.port {
+            return false;
+        }
+        let (window, bank) = self.window_size.decode(value);
+        self.banks[window] = bank % self.bank_count();
+        true
+    }
+
+    /// Current mapping of every window
+    pub fn mapping(&self) -> Vec<BankMapping> {
+        let bytes = self.window_size.bytes();
+        self.banks
+            .iter()
+            .enumerate()
+            .map(|(window, &bank)| BankMapping {
+                window,
+                cpu_start: (window * bytes) as u16,
+                cpu_end: (window * bytes + bytes - 1) as u16,
+                bank,
+                physical_start: bank * bytes,
+            })
+            .collect()
+    }
+
+    /// Physical address a CPU address currently refers to
+    pub fn physical_address(&self, address: u16) -> usize {
+        let bytes = self.window_size.bytes();
+        let address = address as usize;
+        self.banks[address / bytes] * bytes + address % bytes
+    }
+
+    fn translate(&self, address: usize, kind: AccessKind) -> Result<usize, EmuError> {
+        u16::try_from(address)
+            .map(|address| self.physical_address(address))
+            .map_err(|_| EmuError::OutOfBounds { addr: address, kind })
+    }
+}
+
+impl Bus for BankedMemory {
+    fn read(&mut self, address: usize) -> Result<u8, EmuError> {
+        self.peek(address)
+    }
+
+    fn write(&mut self, address: usize, value: u8) -> Result<(), EmuError> {
+        let physical = self.translate(address, AccessKind::Write)?;
+        self.physical[physical] = value;
+        Ok(())
+    }
+
+    fn peek(&self, address: usize) -> Result<u8, EmuError> {
+        Ok(self.physical[self.translate(address, AccessKind::Read)?])
+    }
+
+    fn banks(&self) -> Vec<BankMapping> {
+        self.mapping()
+    }
+
+    fn write_port(&mut self, port: u8, value: u8) -> bool {
+        BankedMemory::write_port(self, port, value)
+    }
+}
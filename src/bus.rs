@@ -0,0 +1,89 @@
+use crate::banked_memory::BankMapping;
+use crate::error::EmuError;
+use crate::memory::Memory;
+
+/// Address space seen by the CPU.
+///
+/// `read` and `write` may have side effects (a device register clearing on
+/// read, a bank switch on write), so debuggers and disassemblers use `peek`.
+pub trait Bus {
+    /// Read a byte as the CPU would
+    fn read(&mut self, address: usize) -> Result<u8, EmuError>;
+
+    /// Write a byte as the CPU would
+    fn write(&mut self, address: usize, value: u8) -> Result<(), EmuError>;
+
+    /// Read a byte without side effects
+    fn peek(&self, address: usize) -> Result<u8, EmuError>;
+
+    /// Number of addressable bytes, starting at 0
+    fn size(&self) -> usize {
+        0x10000
+    }
+
+    /// Read a 16-bit little-endian value
+    fn read_u16(&mut self, address: usize) -> Result<u16, EmuError> {
+        let low = self.read(address)? as u16;
+        let high = self.read(address + 1)? as u16;
+        Ok((high << 8) | low)
+    }
+
+    /// Write a 16-bit little-endian value
+    fn write_u16(&mut self, address: usize, value: u16) -> Result<(), EmuError> {
+        let [low, high] = value.to_le_bytes();
+        self.write(address, low)?;
+        self.write(address + 1, high)
+    }
+
+    /// Copy a block of bytes into the address space through `write`
+    fn load(&mut self, address: usize, bytes: &[u8]) -> Result<(), EmuError> {
+        for (offset, &byte) in bytes.iter().enumerate() {
+            self.write(address + offset, byte)?;
+        }
+        Ok(())
+    }
+
+    /// Current bank-switching windows, for the debugger; empty when nothing is switched
+    fn banks(&self) -> Vec<BankMapping> {
+        Vec::new()
+    }
+
+    /// Offer an OUT to the memory system, returning true if it claimed the port
+    /// (as a bank latch does); unclaimed writes go on to the port bus
+    fn write_port(&mut self, _port: u8, _value: u8) -> bool {
+        false
+    }
+
+    /// Let attached devices advance by `cycles` clock cycles; called after every CPU step
+    fn tick(&mut self, _cycles: u32) {}
+}
+
+impl Bus for Memory {
+    fn read(&mut self, address: usize) -> Result<u8, EmuError> {
+        Memory::read(self, address)
+    }
+
+    fn write(&mut self, address: usize, value: u8) -> Result<(), EmuError> {
+        Memory::write(self, address, value)
+    }
+
+    fn peek(&self, address: usize) -> Result<u8, EmuError> {
+        Memory::read(self, address)
+    }
+
+    fn size(&self) -> usize {
+        Memory::size(self)
+    }
+
+    fn read_u16(&mut self, address: usize) -> Result<u16, EmuError> {
+        Memory::read_u16(self, address)
+    }
+
+    fn write_u16(&mut self, address: usize, value: u16) -> Result<(), EmuError> {
+        Memory::write_u16(self, address, value)
+    }
+
+    fn load(&mut self, address: usize, bytes: &[u8]) -> Result<(), EmuError> {
+        Memory::load(self, address, bytes)
+    }
+}
@@ -1,97 +1,275 @@
-﻿use crate::memory::Memory; // Import the memory module
+﻿use std::collections::BTreeSet;
+use std::time::Duration;
 
-pub struct CPU {
+use crate::bus::Bus;
+use crate::error::{AccessKind, EmuError};
+use crate::interrupt::{IRQ_LINES, InterruptController};
+use crate::memory::Memory; // Import the memory module
+use crate::opcodes as op; // Opcode numbers shared with the assembler and disassembler
+use crate::ports::PortBus;
+
+/// Status register bits held in `CPU::flags`.
+///
+/// Flag behaviour per instruction:
+/// - LOAD, LDA, IN: Z and N from the loaded value; C and V unchanged.
+/// - ADD, ADC: Z and N from the result; C is the unsigned carry out of bit 7;
+///   V is set when two operands of the same sign give a result of the other sign.
+/// - SUB, SBC, CMP: as ADD, but C is set on borrow (unsigned `acc < operand`)
+///   and V on signed overflow of `acc - operand`. CMP discards the result.
+/// - INC, DEC: Z, N and V from the result; C unchanged.
+/// - AND, OR, XOR: Z and N from the result; C and V cleared.
+/// - MUL: Z and N from the low byte; C and V set if the product exceeds 8 bits.
+/// - DIV: Z and N from the quotient; C and V cleared.
+/// - RETI: restores the flags saved when the interrupt was taken.
+///
+/// All other instructions leave the flags untouched.
+pub const FLAG_ZERO: u8 = 0x01; // Result was zero
+pub const FLAG_CARRY: u8 = 0x02; // Unsigned carry out / borrow
+pub const FLAG_NEGATIVE: u8 = 0x04; // Bit 7 of the result
+pub const FLAG_OVERFLOW: u8 = 0x08; // Signed overflow
+
+/// Clock frequency of the MicroBee's Z80, the default for `CPU::clock_hz`
+pub const MICROBEE_CLOCK_HZ: u64 = 3_375_000;
+
+/// Bytes in the IRQ vector table: one 16-bit handler address per line
+pub const VECTOR_TABLE_SIZE: usize = IRQ_LINES as usize * 2;
+
+/// Bit set in the status byte pushed by an interrupt when interrupts were enabled
+const STATUS_INTERRUPTS_ENABLED: u8 = 0x80;
+
+/// Result of a single `CPU::step`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepOutcome {
+    Executed { cycles: u32 }, // An instruction (or interrupt entry) completed
+    Halted,                   // The CPU is halted; nothing was executed
+    Breakpoint,               // An instruction completed and PC is now on a breakpoint
+    Trapped(EmuError),        // Execution failed; PC may point past the faulting opcode
+    WaitingForInterrupt,      // WAIT is idling until an IRQ is serviced
+}
+
+/// The CPU core, generic over the bus it is attached to
+#[allow(clippy::upper_case_acronyms)]
+pub struct CPU<B = Memory> {
     pub pc: u16,        // Program counter
     pub acc: u8,        // Accumulator register
     pub reg_a: u8,  // Additional register
     pub reg_b: u8,  // Additional register
-    pub memory: Memory, // Memory module
+    pub flags: u8,      // Status register (FLAG_* bits)
+    pub memory: B,      // Memory bus
+    pub ports: PortBus, // I/O port space reached by IN and OUT
     pub halted: bool,   // Halt flag to stop the CPU
+    pub waiting: bool,  // Set by WAIT until an interrupt is serviced
+    pub breakpoints: BTreeSet<u16>, // Addresses where `step` reports `Breakpoint`
     pub sp: u16,  //  Stack Pointer
+    pub stack_top: u16,   // Initial SP; the stack grows down from here (0 = top of a 64K space)
+    pub stack_limit: u16, // Lowest address the stack may grow into
     pub interrupts_enabled: bool, // New field to track interrupt state
+    pub interrupts: InterruptController, // IRQ lines sampled between instructions
+    pub vector_base: u16, // Address of the IRQ vector table (one 16-bit handler address per line)
+    pub cycles: u64,      // Clock cycles elapsed since reset
+    pub clock_hz: u64,    // Emulated clock frequency, used to convert cycles to time
+    extra_cycles: u32,    // Memory access and branch costs of the instruction being executed
 }
 
 impl CPU {
     /// Create a new instance of the CPU with a specified memory size
     pub fn new(memory_size: usize) -> Self {
+        CPU::with_bus(Memory::new(memory_size))
+    }
+}
+
+impl<B: Bus> CPU<B> {
+    /// Create a CPU attached to `memory`.
+    ///
+    /// The IRQ vector table takes the top `VECTOR_TABLE_SIZE` bytes of memory,
+    /// clear of images loaded at the bottom. The stack is full-descending and
+    /// starts just below it: a push first decrements SP and then writes, a pop
+    /// reads and then increments.
+    pub fn with_bus(memory: B) -> Self {
+        let vector_base = memory.size().min(0x10000).saturating_sub(VECTOR_TABLE_SIZE) as u16;
+        let stack_top = vector_base;
         CPU {
             pc: 0,
             acc: 0,
             reg_a: 0,
             reg_b: 0,
-            memory: Memory::new(memory_size),
+            flags: FLAG_ZERO, // Matches the accumulator's reset value of 0
+            memory,
+            ports: PortBus::new(),
             halted: false,
-            sp: 0,
+            waiting: false,
+            breakpoints: BTreeSet::new(),
+            sp: stack_top,
+            stack_top,
+            stack_limit: 0,
             interrupts_enabled: false, // Interrupts are initially disabled
-
+            interrupts: InterruptController::new(),
+            vector_base,
+            cycles: 0,
+            clock_hz: MICROBEE_CLOCK_HZ,
+            extra_cycles: 0,
         }
     }
 
     /// Fetch a single byte of instruction data from memory
-    pub fn fetch(&mut self) -> Result<u8, String> {
+    pub fn fetch(&mut self) -> Result<u8, EmuError> {
         let instruction = self.memory.read(self.pc as usize)?;
         self.pc = self.pc.wrapping_add(1); // Increment the program counter (with wrapping)
         Ok(instruction)
     }
 
     /// Fetch a 16-bit address from memory (two bytes in little-endian format)
-    fn fetch_address(&mut self) -> Result<u16, String> {
+    fn fetch_address(&mut self) -> Result<u16, EmuError> {
         let low_byte = self.fetch()? as u16;
         let high_byte = self.fetch()? as u16;
         Ok((high_byte << 8) | low_byte) // Combine high and low bytes
     }
+    /// Return whether the given status flag is set
+    pub fn flag(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    fn set_flag(&mut self, flag: u8, on: bool) {
+        if on {
+            self.flags |= flag;
+        } else {
+            self.flags &= !flag;
+        }
+    }
+
+    /// Update Z and N from a result byte
+    fn set_zn(&mut self, value: u8) {
+        self.set_flag(FLAG_ZERO, value == 0);
+        self.set_flag(FLAG_NEGATIVE, value & 0x80 != 0);
+    }
+
+    /// Add `value` (plus an incoming carry) to the accumulator, updating all flags
+    fn add_to_acc(&mut self, value: u8, carry_in: bool) {
+        let sum = self.acc as u16 + value as u16 + carry_in as u16;
+        let result = sum as u8;
+        let overflow = (self.acc ^ result) & (value ^ result) & 0x80 != 0;
+        self.set_flag(FLAG_CARRY, sum > 0xFF);
+        self.set_flag(FLAG_OVERFLOW, overflow);
+        self.set_zn(result);
+        self.acc = result;
+    }
+
+    /// Compute `acc - value - borrow_in`, updating all flags, and return the result
+    fn sub_from_acc(&mut self, value: u8, borrow_in: bool) -> u8 {
+        let subtrahend = value as u16 + borrow_in as u16;
+        let result = (self.acc as u16).wrapping_sub(subtrahend) as u8;
+        let overflow = (self.acc ^ value) & (self.acc ^ result) & 0x80 != 0;
+        self.set_flag(FLAG_CARRY, (self.acc as u16) < subtrahend);
+        self.set_flag(FLAG_OVERFLOW, overflow);
+        self.set_zn(result);
+        result
+    }
+
+    /// Fetch an address operand and read the byte it points to
+    fn fetch_operand(&mut self) -> Result<u8, EmuError> {
+        let address = self.fetch_address()?;
+        self.extra_cycles += op::MEMORY_ACCESS_CYCLES;
+        self.memory.read(address as usize)
+    }
+
+    /// Flags after AND/OR/XOR: Z and N from the accumulator, C and V cleared
+    fn set_logic_flags(&mut self) {
+        self.set_flag(FLAG_CARRY, false);
+        self.set_flag(FLAG_OVERFLOW, false);
+        self.set_zn(self.acc);
+    }
+
     fn mov(&mut self) {
         self.reg_b = self.reg_a; // Example: Move reg_a's value to reg_b
     }
 
     fn mul(&mut self) {
-        self.acc = self.reg_a.wrapping_mul(self.reg_b); // Handle overflow with wrapping
+        let product = self.reg_a as u16 * self.reg_b as u16;
+        self.acc = product as u8; // Keep the low byte, report the rest through C/V
+        self.set_flag(FLAG_CARRY, product > 0xFF);
+        self.set_flag(FLAG_OVERFLOW, product > 0xFF);
+        self.set_zn(self.acc);
     }
-    fn div(&mut self) -> Result<(), String> {
+    fn div(&mut self) -> Result<(), EmuError> {
         if self.reg_b == 0 {
-            return Err("Division by zero".to_string());
+            return Err(EmuError::DivideByZero { pc: self.pc.wrapping_sub(1) });
         }
         self.acc = self.reg_a / self.reg_b;
+        self.set_flag(FLAG_CARRY, false);
+        self.set_flag(FLAG_OVERFLOW, false);
+        self.set_zn(self.acc);
         Ok(())
     }
-    fn cmp(&self) -> Result<(), String> {
-        // Perform the comparison, but don't return an `i8`
+    fn cmp(&mut self) -> Result<(), EmuError> {
+        let value = self.fetch_operand()?;
+        self.sub_from_acc(value, false); // Flags only, the accumulator is kept
         Ok(())
     }
-    fn call(&mut self) -> Result<(), String> {
+    fn call(&mut self) -> Result<(), EmuError> {
         let address = self.fetch_address()?; // Fetch target address (of type u16)
-        if self.sp < 2 {                     // Ensure enough stack space
-            return Err("Stack overflow - not enough space to push PC".to_string());
-        }
-        self.sp -= 2;                        // Decrement stack pointer
-        self.memory.write_u16(self.sp as usize, self.pc)?; // Push PC onto the stack
+        self.push_u16(self.pc)?;             // Push the return address
         self.pc = address;                   // Set PC to the subroutine address (of type u16)
         Ok(())                               // Return success
     }
-    fn ret(&mut self) -> Result<(), String> {
-        self.sp += 2;                                // Increment stack pointer
-        self.memory.write_u16(self.sp as usize, self.pc)?; // Pop PC
+    fn ret(&mut self) -> Result<(), EmuError> {
+        self.pc = self.pop_u16()?; // Pop the return address
         Ok(())
     }
-    fn jp(&mut self, condition: bool) {
+    /// Jump to the address operand if `condition` holds; the operand is always consumed
+    fn jump_if(&mut self, condition: bool) -> Result<(), EmuError> {
+        let address = self.fetch_address()?;
         if condition {
-            self.pc = self.fetch_address().unwrap();
+            self.pc = address;
+            self.extra_cycles += op::BRANCH_TAKEN_CYCLES;
         }
+        Ok(())
     }
-    fn jn(&mut self, condition: bool) {
-        if condition {
-            self.pc = self.fetch_address().unwrap();
+    /// Software interrupt: enter the handler at the operand exactly like a hardware IRQ
+    fn int(&mut self) -> Result<(), EmuError> {
+        let interrupt_vector = self.fetch_address()?; // Safely fetch interrupt vector
+        self.enter_interrupt(interrupt_vector)
+    }
+
+    /// Push PC and the status byte, disable interrupts and jump to `handler`
+    fn enter_interrupt(&mut self, handler: u16) -> Result<(), EmuError> {
+        let mut status = self.flags;
+        if self.interrupts_enabled {
+            status |= STATUS_INTERRUPTS_ENABLED;
         }
+        self.push_u16(self.pc)?;
+        self.push(status)?;
+        self.interrupts_enabled = false; // Handlers are not re-entered until RETI or SEI
+        self.pc = handler;
+        Ok(())
     }
-    fn int(&mut self) -> Result<(), String> {
-        let interrupt_vector = self.fetch_address()?; // Safely fetch interrupt vector
-        self.memory
-            .write_u16(self.sp as usize, self.pc)
-            .map_err(|_| "Failed to write to memory".to_string())?; // Handle memory write errors
-        self.sp = self.sp.checked_sub(2).ok_or("Stack underflow")?; // Safely decrement SP
-        self.pc = interrupt_vector; // Jump to interrupt handler
+
+    /// RETI: pop the status byte and PC pushed by `enter_interrupt`
+    fn reti(&mut self) -> Result<(), EmuError> {
+        let status = self.pop()?;
+        self.pc = self.pop_u16()?;
+        self.flags = status & !STATUS_INTERRUPTS_ENABLED;
+        self.interrupts_enabled = status & STATUS_INTERRUPTS_ENABLED != 0;
         Ok(())
     }
+
+    /// Service the highest priority pending IRQ if interrupts are enabled.
+    ///
+    /// Returns the line that was serviced, if any. The request is only
+    /// cleared once the handler has been entered, so one that fails stays
+    /// pending.
+    pub fn poll_interrupts(&mut self) -> Result<Option<u8>, EmuError> {
+        if !self.interrupts_enabled {
+            return Ok(None);
+        }
+        let Some(line) = self.interrupts.pending() else {
+            return Ok(None);
+        };
+        let vector = self.vector_base.wrapping_add(line as u16 * 2);
+        let handler = self.memory.read_u16(vector as usize)?;
+        self.enter_interrupt(handler)?;
+        self.interrupts.clear(line);
+        Ok(Some(line))
+    }
     fn cli(&mut self) {
         self.interrupts_enabled = false;
     }
@@ -99,171 +277,409 @@ impl CPU {
     fn sei(&mut self) {
         self.interrupts_enabled = true;
     }
-    fn push(&mut self, value: u8) -> Result<(), String> {
-        self.sp = self.sp.checked_sub(1).ok_or("Stack underflow")?; // Safely decrement Stack Pointer
-        self.memory
-            .write(self.sp as usize, value) // Write value to memory
-            .map_err(|e| format!("Failed to push value to memory: {}", e)) // Handle memory write errors
+    /// Place the stack between `limit` and `top` and reset SP to `top`, discarding its contents
+    pub fn set_stack(&mut self, top: u16, limit: u16) {
+        self.stack_top = top;
+        self.stack_limit = limit;
+        self.sp = top;
     }
-    fn pop(&mut self) -> Result<u8, String> {
-        // Implementation of popping data directly, no need to pass a mutable borrow of the target field
-        let value = self.memory.read(self.sp as usize)?; // Example logic
-        self.sp = self.sp.wrapping_add(1); // Modify the stack pointer
-        Ok(value)
+
+    /// Number of bytes currently on the stack
+    fn stack_depth(&self) -> u32 {
+        self.stack_top.wrapping_sub(self.sp) as u32
     }
 
-    /// Execute the given instruction based on its opcode
-    pub fn execute(&mut self, instruction: u8) -> Result<(), String> {
+    /// Number of bytes the stack region can hold
+    fn stack_capacity(&self) -> u32 {
+        match self.stack_top.wrapping_sub(self.stack_limit) {
+            0 => 0x10000, // top == limit covers the whole address space
+            size => size as u32,
+        }
+    }
+
+    /// Make room for `bytes` on the stack and return the new SP
+    fn grow_stack(&mut self, bytes: u16) -> Result<u16, EmuError> {
+        if self.stack_depth() + bytes as u32 > self.stack_capacity() {
+            return Err(EmuError::StackOverflow { sp: self.sp });
+        }
+        self.sp = self.sp.wrapping_sub(bytes);
+        Ok(self.sp)
+    }
+
+    /// Release `bytes` from the stack and return the SP they were stored at
+    fn shrink_stack(&mut self, bytes: u16) -> Result<u16, EmuError> {
+        if self.stack_depth() < bytes as u32 {
+            return Err(EmuError::StackUnderflow { sp: self.sp });
+        }
+        let address = self.sp;
+        self.sp = self.sp.wrapping_add(bytes);
+        Ok(address)
+    }
+
+    fn push(&mut self, value: u8) -> Result<(), EmuError> {
+        let address = self.grow_stack(1)?;
+        self.memory.write(address as usize, value)
+    }
+    fn pop(&mut self) -> Result<u8, EmuError> {
+        let address = self.shrink_stack(1)?;
+        self.memory.read(address as usize)
+    }
+    fn push_u16(&mut self, value: u16) -> Result<(), EmuError> {
+        let address = self.grow_stack(2)?;
+        self.memory.write_u16(address as usize, value)
+    }
+    fn pop_u16(&mut self) -> Result<u16, EmuError> {
+        let address = self.shrink_stack(2)?;
+        self.memory.read_u16(address as usize)
+    }
+
+    /// Execute the given instruction based on its opcode.
+    ///
+    /// Every arm must have a matching entry in `opcodes::OPCODES`.
+    pub fn execute(&mut self, instruction: u8) -> Result<(), EmuError> {
         match instruction {
             // LOAD: Load a value from memory into the accumulator
-            0x01 => {
-                let address = self.fetch_address()?;
-                self.acc = self.memory.read(address as usize)?;
-
+            op::LOAD => {
+                self.acc = self.fetch_operand()?;
+                self.set_zn(self.acc);
             }
 
             // STORE: Store the accumulator value into a memory address
-            0x02 => {
+            op::STORE => {
                 let address = self.fetch_address()?;
+                self.extra_cycles += op::MEMORY_ACCESS_CYCLES;
                 self.memory.write(address as usize, self.acc)?;
             }
 
             // ADD: Add a value from memory to the accumulator
-            0x03 => {
-                let address = self.fetch_address()?;
-                let value = self.memory.read(address as usize)?;
-                self.acc = self.acc.wrapping_add(value);
+            op::ADD => {
+                let value = self.fetch_operand()?;
+                self.add_to_acc(value, false);
             }
 
             // SUB: Subtract a value from memory from the accumulator
-            0x04 => {
-                let address = self.fetch_address()?;
-                let value = self.memory.read(address as usize)?;
-                self.acc = self.acc.wrapping_sub(value);
+            op::SUB => {
+                let value = self.fetch_operand()?;
+                self.acc = self.sub_from_acc(value, false);
+            }
+
+            // ADC: Add a value from memory plus the carry flag to the accumulator
+            op::ADC => {
+                let value = self.fetch_operand()?;
+                self.add_to_acc(value, self.flag(FLAG_CARRY));
+            }
+
+            // SBC: Subtract a value from memory and the carry (borrow) flag from the accumulator
+            op::SBC => {
+                let value = self.fetch_operand()?;
+                self.acc = self.sub_from_acc(value, self.flag(FLAG_CARRY));
             }
 
             // INC: Increment the accumulator by 1
-            0x07 => {
+            op::INC => {
+                self.set_flag(FLAG_OVERFLOW, self.acc == 0x7F);
                 self.acc = self.acc.wrapping_add(1);
+                self.set_zn(self.acc);
             }
 
             // DEC: Decrement the accumulator by 1
-            0x08 => {
+            op::DEC => {
+                self.set_flag(FLAG_OVERFLOW, self.acc == 0x80);
                 self.acc = self.acc.wrapping_sub(1);
+                self.set_zn(self.acc);
             }
 
             // AND: Logical AND between the accumulator and a memory value
-            0x09 => {
-                let address = self.fetch_address()?;
-                let value = self.memory.read(address as usize)?;
+            op::AND => {
+                let value = self.fetch_operand()?;
                 self.acc &= value;
+                self.set_logic_flags();
             }
 
             // OR: Logical OR between the accumulator and a memory value
-            0x0A => {
-                let address = self.fetch_address()?;
-                let value = self.memory.read(address as usize)?;
+            op::OR => {
+                let value = self.fetch_operand()?;
                 self.acc |= value;
+                self.set_logic_flags();
             }
 
             // XOR: Logical XOR between the accumulator and a memory value
-            0x0B => {
-                let address = self.fetch_address()?;
-                let value = self.memory.read(address as usize)?;
+            op::XOR => {
+                let value = self.fetch_operand()?;
                 self.acc ^= value;
+                self.set_logic_flags();
             }
 
             // JMP: Jump to the specified memory address
-            0x10 => {
+            op::JMP => {
                 let address = self.fetch_address()?;
                 self.pc = address;
             }
 
-            // JZ: Jump to an address if the accumulator is zero
-            0x11 => {
-                let address = self.fetch_address()?;
-                if self.acc == 0 {
-                    self.pc = address;
-                }
-            }
+            // JZ: Jump to an address if the zero flag is set
+            op::JZ => self.jump_if(self.flag(FLAG_ZERO))?,
 
-            // JNZ: Jump to an address if the accumulator is not zero
-            0x12 => {
-                let address = self.fetch_address()?;
-                if self.acc != 0 {
-                    self.pc = address;
-                }
-            }
+            // JNZ: Jump to an address if the zero flag is clear
+            op::JNZ => self.jump_if(!self.flag(FLAG_ZERO))?,
 
             // LDA: Load a value directly into the accumulator
-            0x13 => {
+            op::LDA => {
                 self.acc = self.fetch()?;
+                self.set_zn(self.acc);
             }
-            0x14 => self.mov(),               // MOV instruction
-            0x15 => self.mul(),               // MUL instruction
-            0x16 => self.div()?,              // DIV instruction
-            0x17 => self.cmp()?,               // CMP instruction
-            0x18 => self.call()?,             // CALL instruction
-            0x19 => self.ret()?,              // RET instruction
-            0x1A => self.jp(true),            // JP (Jump if Positive)
-            0x1B => self.jn(true),            // JN (Jump if Negative)
-            0x1C => self.int()?,               // INT (Interrupt)
-            0x1D => self.cli(),               // CLI (Disable Interrupts)
-            0x1E => self.sei(),               // SEI (Enable Interrupts)
-            0x1F => self.push(self.reg_a)?,    // PUSH reg_a
-            0x20 => {
+            op::MOV => self.mov(),            // MOV instruction
+            op::MUL => self.mul(),            // MUL instruction
+            op::DIV => self.div()?,           // DIV instruction
+            op::CMP => self.cmp()?,           // CMP: compare accumulator with memory
+            op::CALL => self.call()?,         // CALL instruction
+            op::RET => self.ret()?,           // RET instruction
+            op::JP => self.jump_if(!self.flag(FLAG_NEGATIVE))?, // JP (Jump if Positive, N clear)
+            op::JN => self.jump_if(self.flag(FLAG_NEGATIVE))?,  // JN (Jump if Negative, N set)
+            op::INT => self.int()?,           // INT (Interrupt)
+            op::CLI => self.cli(),            // CLI (Disable Interrupts)
+            op::SEI => self.sei(),            // SEI (Enable Interrupts)
+            op::PUSH => self.push(self.reg_a)?,    // PUSH reg_a
+            op::POP => {
                 let value = self.pop()?;      // First, pop the value from the stack
                 self.reg_a = value;           // Then, assign it to reg_a
             }, // POP reg_a
+            op::JC => self.jump_if(self.flag(FLAG_CARRY))?,  // JC (Jump if Carry)
+            op::JNC => self.jump_if(!self.flag(FLAG_CARRY))?, // JNC (Jump if No Carry)
+            op::JLT => self.jump_if(self.flag(FLAG_NEGATIVE) != self.flag(FLAG_OVERFLOW))?, // JLT (signed less than)
+            op::JGE => self.jump_if(self.flag(FLAG_NEGATIVE) == self.flag(FLAG_OVERFLOW))?, // JGE (signed greater or equal)
+            op::RETI => self.reti()?,         // RETI (Return from Interrupt)
+            op::IN => {
+                let port = self.fetch()?;
+                self.acc = self.ports.read(port)?;
+                self.set_zn(self.acc);
+            }
+            op::OUT => {
+                let port = self.fetch()?;
+                self.port_out(port, self.acc)?;
+            }
+            op::WAIT => self.waiting = true,  // WAIT (Idle until an interrupt)
 
 
             // HALT: Stop the CPU
-            0xFF => {
+            op::HALT => {
                 self.halted = true;
             }
 
             // Handle unknown instructions
             _ => {
-                return Err(format!("Unknown instruction: 0x{:02X} at PC: 0x{:04X}", instruction, self.pc));
+                return Err(EmuError::UnknownOpcode {
+                    opcode: instruction,
+                    pc: self.pc.wrapping_sub(1), // Address of the opcode itself
+                });
             }
         }
         Ok(())
     }
 
-    /// Run the CPU loop until the `halted` state is true
-    pub fn run(&mut self) {
-        while !self.halted {
-            match self.fetch() {
-                Ok(instruction) => {
-                    if let Err(err) = self.execute(instruction) {
-                        println!("Execution error: {}", err);
-                        self.halted = true; // Stop the CPU on error
-                    }
-                }
-                Err(err) => {
-                    println!("Fetch error: {}", err);
+    /// Execute one instruction, servicing a pending interrupt first.
+    ///
+    /// A breakpoint is reported once PC reaches it, so calling `step` again
+    /// executes the instruction under the breakpoint and moves on.
+    pub fn step(&mut self) -> StepOutcome {
+        if self.halted {
+            return StepOutcome::Halted;
+        }
+        let spent = match self.poll_interrupts() {
+            Ok(Some(_)) => {
+                self.waiting = false;
+                op::INTERRUPT_CYCLES
+            }
+            // Only the program can enable interrupts, so nothing could end this WAIT
+            Ok(None) if self.waiting && !self.interrupts_enabled => {
+                return StepOutcome::Trapped(EmuError::WaitWithInterruptsDisabled { pc: self.pc.wrapping_sub(1) });
+            }
+            Ok(None) if self.waiting => {
+                self.advance(op::WAIT_IDLE_CYCLES);
+                return StepOutcome::WaitingForInterrupt;
+            }
+            Ok(None) => match self.execute_next() {
+                Ok(spent) => spent,
+                Err(err) => return StepOutcome::Trapped(err),
+            },
+            Err(err) => return StepOutcome::Trapped(err),
+        };
+        self.advance(spent);
+        if self.halted {
+            StepOutcome::Halted
+        } else if self.breakpoints.contains(&self.pc) {
+            StepOutcome::Breakpoint
+        } else {
+            StepOutcome::Executed { cycles: spent }
+        }
+    }
+
+    /// Count `cycles` and let the devices on the bus catch up
+    fn advance(&mut self, cycles: u32) {
+        self.cycles += cycles as u64;
+        self.memory.tick(cycles);
+        self.ports.tick(cycles);
+        self.ports.dma(&mut self.memory);
+    }
+
+    /// Write `value` to `port`, offering it to the memory system first since
+    /// bank latches live there
+    pub fn port_out(&mut self, port: u8, value: u8) -> Result<(), EmuError> {
+        if self.memory.write_port(port, value) {
+            self.ports.record(port, value, AccessKind::Write, true);
+            Ok(())
+        } else {
+            self.ports.write(port, value)
+        }
+    }
+
+    /// Fetch and execute the instruction at PC, returning the cycles it took
+    fn execute_next(&mut self) -> Result<u32, EmuError> {
+        let instruction = self.fetch()?;
+        self.extra_cycles = 0;
+        self.execute(instruction)?;
+        let base = op::lookup(instruction).map_or(0, |info| info.cycles);
+        Ok(base + self.extra_cycles)
+    }
+
+    /// Step until `stop` returns true after an instruction, or until the CPU
+    /// halts, traps or hits a breakpoint. Returns the last outcome.
+    ///
+    /// Waiting for an interrupt does not end the run, so `stop` must bound it
+    /// if nothing will raise one.
+    pub fn run_until(&mut self, mut stop: impl FnMut(&Self) -> bool) -> StepOutcome {
+        loop {
+            let outcome = self.step();
+            match outcome {
+                StepOutcome::Executed { .. } | StepOutcome::WaitingForInterrupt if !stop(self) => {}
+                _ => return outcome,
+            }
+        }
+    }
+
+    /// Run until at least `cycles` more cycles have elapsed; see `run_until`
+    pub fn run_for(&mut self, cycles: u64) -> StepOutcome {
+        if cycles == 0 {
+            return StepOutcome::Executed { cycles: 0 };
+        }
+        let target = self.cycles + cycles;
+        self.run_until(|cpu| cpu.cycles >= target)
+    }
+
+    /// Emulated time elapsed since reset at `clock_hz`
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_secs_f64(self.cycles as f64 / self.clock_hz as f64)
+    }
+
+    /// Number of clock cycles in `duration` at `clock_hz`
+    pub fn cycles_in(&self, duration: Duration) -> u64 {
+        (duration.as_secs_f64() * self.clock_hz as f64).round() as u64
+    }
+
+    /// Run until the CPU halts, stopping with the error if it traps.
+    ///
+    /// A WAIT with interrupts enabled idles until an IRQ arrives, so a device
+    /// must be able to raise one or this never returns.
+    pub fn run(&mut self) -> Result<(), EmuError> {
+        loop {
+            match self.step() {
+                StepOutcome::Halted => return Ok(()),
+                StepOutcome::Trapped(err) => {
                     self.halted = true; // Stop the CPU on error
+                    return Err(err);
                 }
+                _ => {}
             }
         }
     }
 
+    /// Describe the registers and flags on one line
+    pub fn format_registers(&self) -> String {
+        let flag = |bit, name| if self.flag(bit) { name } else { '-' };
+        format!(
+            "PC={:04X} SP={:04X} ACC={:02X} A={:02X} B={:02X} FLAGS={}{}{}{} IE={} HALTED={} CYC={}",
+            self.pc,
+            self.sp,
+            self.acc,
+            self.reg_a,
+            self.reg_b,
+            flag(FLAG_ZERO, 'Z'),
+            flag(FLAG_CARRY, 'C'),
+            flag(FLAG_NEGATIVE, 'N'),
+            flag(FLAG_OVERFLOW, 'V'),
+            self.interrupts_enabled as u8,
+            self.halted as u8,
+            self.cycles,
+        )
+    }
+
+    /// Debugging tool to print the registers and flags on one line
+    pub fn print_registers(&self) {
+        println!("{}", self.format_registers());
+    }
+
     /// Debugging tool to print a chunk of memory content (hex values)
     #[allow(dead_code)]
-
     pub fn print_memory(&self, start: usize, count: usize) {
         for i in start..(start + count) {
-            match self.memory.read(i) {
+            match self.memory.peek(i) {
                 Ok(value) => print!("{:02X} ", value),
                 Err(err) => {
                     println!("Failed to read memory at 0x{:04X}: {}", i, err);
                     break;
                 }
             }
-            if (i - start + 1) % 16 == 0 {
+            if (i - start + 1).is_multiple_of(16) {
                 println!(); // Newline after 16 bytes
             }
         }
         println!(); // Final newline
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_flag_matches_the_accumulator_at_reset() {
+        let mut cpu = CPU::new(0x100);
+        cpu.memory.load(0, &[op::JZ, 0x10, 0x00]).unwrap();
+        cpu.step();
+        assert_eq!(cpu.pc, 0x10);
+    }
+
+    #[test]
+    fn vector_table_and_stack_sit_at_the_top_of_memory() {
+        let cpu = CPU::new(0x8000);
+        assert_eq!(cpu.vector_base, 0x7FF0);
+        assert_eq!(cpu.sp, 0x7FF0);
+    }
+
+    #[test]
+    fn interrupt_is_taken_through_its_vector_and_acknowledged() {
+        let mut cpu = CPU::new(0x10000);
+        cpu.memory.write_u16(cpu.vector_base as usize + 2 * 3, 0x1234).unwrap();
+        cpu.interrupts_enabled = true;
+        cpu.interrupts.raise(3);
+        assert_eq!(cpu.poll_interrupts(), Ok(Some(3)));
+        assert_eq!(cpu.pc, 0x1234);
+        assert_eq!(cpu.interrupts.requests(), 0);
+    }
+
+    #[test]
+    fn unreadable_vector_leaves_the_request_pending() {
+        let mut cpu = CPU::new(0x100);
+        cpu.vector_base = 0x200;
+        cpu.interrupts_enabled = true;
+        cpu.interrupts.raise(0);
+        assert!(cpu.poll_interrupts().is_err());
+        assert_eq!(cpu.interrupts.requests(), 1);
+    }
+
+    #[test]
+    fn wait_with_interrupts_disabled_traps() {
+        let mut cpu = CPU::new(0x100);
+        cpu.memory.load(0, &[op::CLI, op::WAIT]).unwrap();
+        assert_eq!(cpu.run(), Err(EmuError::WaitWithInterruptsDisabled { pc: 1 }));
+    }
 }
\ No newline at end of file
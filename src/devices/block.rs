@@ -0,0 +1,266 @@
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use super::Device;
+use crate::bus::Bus;
+use crate::interrupt::IrqLine;
+
+/// First of the block device's eight ports
+pub const BLOCK_PORT: u8 = 0x60;
+
+/// Bytes per block
+pub const BLOCK_SIZE: usize = 512;
+
+/// Register offsets from `BLOCK_PORT`
+pub const REG_LBA_LOW: usize = 0;      // Block number, 24 bits
+pub const REG_LBA_MID: usize = 1;
+pub const REG_LBA_HIGH: usize = 2;
+pub const REG_ADDRESS_LOW: usize = 3;  // Memory address of the transfer
+pub const REG_ADDRESS_HIGH: usize = 4;
+pub const REG_COUNT: usize = 5;        // Blocks to transfer
+pub const REG_COMMAND: usize = 6;      // Write a command; read status, which clears STATUS_DONE and the IRQ
+pub const REG_CONTROL: usize = 7;
+
+/// Commands
+pub const COMMAND_READ: u8 = 0x01;     // Copy COUNT blocks from the image to memory
+pub const COMMAND_WRITE: u8 = 0x02;    // Copy COUNT blocks from memory to the image
+pub const COMMAND_CAPACITY: u8 = 0x03; // Load the number of blocks on the image into the LBA registers
+pub const COMMAND_FLUSH: u8 = 0x04;    // Make sure written blocks have reached the host file
+
+/// Status register bits
+pub const STATUS_BUSY: u8 = 0x01;  // A command has been issued and not yet carried out
+pub const STATUS_DONE: u8 = 0x02;  // The last command has finished
+pub const STATUS_ERROR: u8 = 0x80; // The last command failed: bad command, block out of range or host I/O error
+
+/// Control register bits
+pub const CONTROL_IRQ: u8 = 0x01; // Assert the IRQ line when a command finishes
+
+/// Paravirtual block device that copies whole blocks between a host image
+/// file and memory.
+///
+/// The program sets the block number, memory address and block count, then
+/// writes a command. The transfer happens in one go straight after the
+/// instruction that issued it, through the CPU's bus, and sets `STATUS_DONE`
+/// (raising the IRQ if enabled). The registers are left as they were, so
+/// the next transfer only needs the ones that change. Addresses wrap at 64K.
+pub struct BlockDevice {
+    file: File,
+    blocks: u32,
+    read_only: bool,
+    lba: u32,
+    address: u16,
+    count: u8,
+    status: u8,
+    control: u8,
+    command: Option<u8>, // Issued, waiting for the next `dma`
+    pub irq: Option<IrqLine>,
+}
+
+impl BlockDevice {
+    /// Open an image file, creating it with `create_size` zeroed bytes if it does not exist.
+    ///
+    /// The image must be a whole number of blocks. A file that cannot be
+    /// opened for writing is attached read-only.
+    pub fn open(path: &Path, create_size: usize) -> Result<Self, String> {
+        let describe = |err: std::io::Error| format!("{}: {}", path.display(), err);
+        let (file, read_only) = if path.exists() {
+            match OpenOptions::new().read(true).write(true).open(path) {
+                Ok(file) => (file, false),
+                Err(_) => (File::open(path).map_err(describe)?, true),
+            }
+        } else {
+            // Checked before creating so that a bad size does not leave a file behind
+            count_blocks(path, create_size as u64)?;
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(path)
+                .map_err(describe)?;
+            if let Err(err) = file.set_len(create_size as u64) {
+                let _ = std::fs::remove_file(path);
+                return Err(describe(err));
+            }
+            (file, false)
+        };
+        let blocks = count_blocks(path, file.metadata().map_err(describe)?.len())?;
+        Ok(BlockDevice {
+            file,
+            blocks,
+            read_only,
+            lba: 0,
+            address: 0,
+            count: 0,
+            status: 0,
+            control: 0,
+            command: None,
+            irq: None,
+        })
+    }
+
+    /// Number of blocks on the image
+    pub fn blocks(&self) -> u32 {
+        self.blocks
+    }
+
+    /// Whether writes are refused
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn run(&mut self, command: u8, memory: &mut dyn Bus) -> Result<(), String> {
+        let count = self.count as u32;
+        match command {
+            COMMAND_READ | COMMAND_WRITE if self.lba + count > self.blocks => {
+                Err(format!("blocks {}-{} are past the end of the image", self.lba, self.lba + count))
+            }
+            COMMAND_READ => {
+                let mut buffer = vec![0; count as usize * BLOCK_SIZE];
+                self.file
+                    .read_exact_at(&mut buffer, self.lba as u64 * BLOCK_SIZE as u64)
+                    .map_err(|err| err.to_string())?;
+                for (offset, &byte) in buffer.iter().enumerate() {
+                    let address = self.address.wrapping_add(offset as u16);
+                    memory.write(address as usize, byte).map_err(|err| err.to_string())?;
+                }
+                Ok(())
+            }
+            COMMAND_WRITE if self.read_only => Err("image is read-only".to_string()),
+            COMMAND_WRITE => {
+                let buffer = (0..count as usize * BLOCK_SIZE)
+                    .map(|offset| memory.read(self.address.wrapping_add(offset as u16) as usize))
+                    .collect::<Result<Vec<u8>, _>>()
+                    .map_err(|err| err.to_string())?;
+                self.file
+                    .write_all_at(&buffer, self.lba as u64 * BLOCK_SIZE as u64)
+                    .map_err(|err| err.to_string())
+            }
+            COMMAND_CAPACITY => {
+                self.lba = self.blocks;
+                Ok(())
+            }
+            COMMAND_FLUSH => self.file.sync_data().map_err(|err| err.to_string()),
+            _ => Err(format!("unknown command {:02X}", command)),
+        }
+    }
+
+    fn update_irq(&self) {
+        if let Some(irq) = &self.irq {
+            if self.control & CONTROL_IRQ != 0 && self.status & STATUS_DONE != 0 {
+                irq.assert();
+            } else {
+                irq.release();
+            }
+        }
+    }
+}
+
+impl Device for BlockDevice {
+    fn read(&mut self, offset: usize) -> Result<u8, String> {
+        let value = self.peek(offset);
+        if offset == REG_COMMAND {
+            self.status &= !STATUS_DONE;
+            self.update_irq();
+        }
+        Ok(value)
+    }
+
+    fn write(&mut self, offset: usize, value: u8) -> Result<(), String> {
+        let set_byte = |word: u32, shift: u32| (word & !(0xFF << shift)) | ((value as u32) << shift);
+        match offset {
+            REG_LBA_LOW => self.lba = set_byte(self.lba, 0),
+            REG_LBA_MID => self.lba = set_byte(self.lba, 8),
+            REG_LBA_HIGH => self.lba = set_byte(self.lba, 16),
+            REG_ADDRESS_LOW => self.address = set_byte(self.address as u32, 0) as u16,
+            REG_ADDRESS_HIGH => self.address = set_byte(self.address as u32, 8) as u16,
+            REG_COUNT => self.count = value,
+            REG_COMMAND if self.status & STATUS_BUSY == 0 => {
+                self.command = Some(value);
+                self.status = STATUS_BUSY;
+                self.update_irq();
+            }
+            REG_CONTROL => {
+                self.control = value;
+                self.update_irq();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn peek(&self, offset: usize) -> u8 {
+        match offset {
+            REG_LBA_LOW => self.lba as u8,
+            REG_LBA_MID => (self.lba >> 8) as u8,
+            REG_LBA_HIGH => (self.lba >> 16) as u8,
+            REG_ADDRESS_LOW => self.address as u8,
+            REG_ADDRESS_HIGH => (self.address >> 8) as u8,
+            REG_COUNT => self.count,
+            REG_COMMAND => self.status,
+            REG_CONTROL => self.control,
+            _ => 0,
+        }
+    }
+
+    fn dma(&mut self, memory: &mut dyn Bus) {
+        let Some(command) = self.command.take() else {
+            return;
+        };
+        self.status = match self.run(command, memory) {
+            Ok(()) => STATUS_DONE,
+            Err(_) => STATUS_DONE | STATUS_ERROR,
+        };
+        self.update_irq();
+    }
+}
+
+/// Number of blocks in an image of `size` bytes, which must be a whole number
+/// of blocks that 24-bit block numbers can reach
+fn count_blocks(path: &Path, size: u64) -> Result<u32, String> {
+    if !size.is_multiple_of(BLOCK_SIZE as u64) {
+        return Err(format!("{}: size {} is not a multiple of {}", path.display(), size, BLOCK_SIZE));
+    }
+    u32::try_from(size / BLOCK_SIZE as u64)
+        .ok()
+        .filter(|&blocks| blocks < 0x100_0000)
+        .ok_or_else(|| format!("{}: image is larger than 24-bit block numbers can reach", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("mbos-block-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn creates_a_missing_image_of_whole_blocks() {
+        let path = scratch_path("create.img");
+        let device = BlockDevice::open(&path, 4 * BLOCK_SIZE).unwrap();
+        assert_eq!(device.blocks(), 4);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 4 * BLOCK_SIZE as u64);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn bad_create_sizes_leave_no_file_behind() {
+        let path = scratch_path("partial.img");
+        assert!(BlockDevice::open(&path, BLOCK_SIZE + 1).is_err());
+        assert!(!path.exists());
+        assert!(BlockDevice::open(&path, 0x100_0000 * BLOCK_SIZE).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn rejects_existing_images_of_partial_blocks() {
+        let path = scratch_path("existing.img");
+        std::fs::write(&path, [0; 100]).unwrap();
+        assert!(BlockDevice::open(&path, BLOCK_SIZE).is_err());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 100);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
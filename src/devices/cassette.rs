@@ -0,0 +1,160 @@
+use super::Device;
+use crate::interrupt::IrqLine;
+use crate::tape::{BITS_PER_BYTE, Baud, Tape};
+
+/// First of the cassette interface's three ports: data at +0, status at +1, control at +2
+pub const CASSETTE_PORT: u8 = 0x70;
+
+/// Status register bits
+pub const STATUS_RX_READY: u8 = 0x01; // A byte from the tape is waiting in the data register
+pub const STATUS_TX_READY: u8 = 0x02; // The previous byte has been recorded and another can be written
+pub const STATUS_OVERRUN: u8 = 0x04;  // A byte from the tape was lost unread; cleared by reading status
+pub const STATUS_END: u8 = 0x08;      // Every byte on the tape has been played
+
+/// Control register bits
+pub const CONTROL_PLAY: u8 = 0x01;   // Run the tape and deliver its bytes
+pub const CONTROL_RECORD: u8 = 0x02; // Record bytes written to the data register
+pub const CONTROL_IRQ: u8 = 0x04;    // Assert the IRQ line while a played byte is waiting
+
+/// Cassette interface that plays and records whole bytes at tape speed.
+///
+/// Bytes move at the rate the chosen baud gives for eleven-bit frames, so a
+/// program that does not keep up while playing loses bytes, as it would with
+/// a real tape. Playing stops at the end of the tape; recording appends.
+pub struct Cassette {
+    input: Tape,
+    position: usize,           // Next byte of `input` to play
+    recorded: Tape,
+    cycles_per_byte: u64,
+    play_cycles: u64,          // Cycles towards the next byte played
+    record_cycles: u64,        // Cycles left until the byte being recorded is down
+    rx: Option<u8>,
+    tx: Option<u8>,            // Byte being recorded
+    overrun: bool,
+    control: u8,
+    pub irq: Option<IrqLine>,
+}
+
+impl Cassette {
+    /// Create a cassette interface running at `baud` for a CPU at `clock_hz`, with a tape to play
+    pub fn new(input: Tape, baud: Baud, clock_hz: u64) -> Self {
+        Cassette {
+            input,
+            position: 0,
+            recorded: Tape::default(),
+            cycles_per_byte: clock_hz * BITS_PER_BYTE / baud.rate() as u64,
+            play_cycles: 0,
+            record_cycles: 0,
+            rx: None,
+            tx: None,
+            overrun: false,
+            control: 0,
+            irq: None,
+        }
+    }
+
+    /// Bytes recorded so far, including one still being written
+    pub fn recorded(&self) -> Tape {
+        let mut tape = self.recorded.clone();
+        tape.bytes.extend(self.tx);
+        tape
+    }
+
+    /// Bytes of the input tape played so far
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = 0;
+        if self.rx.is_some() {
+            status |= STATUS_RX_READY;
+        }
+        if self.tx.is_none() {
+            status |= STATUS_TX_READY;
+        }
+        if self.overrun {
+            status |= STATUS_OVERRUN;
+        }
+        if self.position >= self.input.bytes.len() {
+            status |= STATUS_END;
+        }
+        status
+    }
+
+    fn update_irq(&self) {
+        if let Some(irq) = &self.irq {
+            if self.control & CONTROL_IRQ != 0 && self.rx.is_some() {
+                irq.assert();
+            } else {
+                irq.release();
+            }
+        }
+    }
+}
+
+impl Device for Cassette {
+    fn read(&mut self, offset: usize) -> Result<u8, String> {
+        match offset {
+            0 => {
+                let byte = self.rx.take().unwrap_or(0);
+                self.update_irq();
+                Ok(byte)
+            }
+            1 => {
+                let status = self.status();
+                self.overrun = false;
+                Ok(status)
+            }
+            _ => Ok(self.peek(offset)),
+        }
+    }
+
+    fn write(&mut self, offset: usize, value: u8) -> Result<(), String> {
+        match offset {
+            // A write while the previous byte is still going down is lost, as on a real UART
+            0 if self.control & CONTROL_RECORD != 0 && self.tx.is_none() => {
+                self.tx = Some(value);
+                self.record_cycles = self.cycles_per_byte;
+            }
+            2 => {
+                self.control = value;
+                self.update_irq();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn peek(&self, offset: usize) -> u8 {
+        match offset {
+            0 => self.rx.unwrap_or(0),
+            1 => self.status(),
+            2 => self.control,
+            _ => 0,
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        if let Some(byte) = self.tx {
+            self.record_cycles = self.record_cycles.saturating_sub(cycles as u64);
+            if self.record_cycles == 0 {
+                self.recorded.bytes.push(byte);
+                self.tx = None;
+            }
+        }
+        if self.control & CONTROL_PLAY == 0 || self.position >= self.input.bytes.len() {
+            return;
+        }
+        self.play_cycles += cycles as u64;
+        while self.play_cycles >= self.cycles_per_byte && self.position < self.input.bytes.len() {
+            self.play_cycles -= self.cycles_per_byte;
+            if self.rx.is_some() {
+                self.overrun = true;
+            }
+            self.rx = Some(self.input.bytes[self.position]);
+            self.position += 1;
+        }
+        self.update_irq();
+    }
+}
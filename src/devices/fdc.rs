@@ -0,0 +1,619 @@
+use super::Device;
+use crate::disk::{DiskImage, size_code};
+use crate::interrupt::IrqLine;
+
+/// First of the controller's ports: command/status, track, sector and data at
+/// +0 to +3, and the MicroBee drive select latch at +4
+pub const FDC_PORT: u8 = 0x44;
+
+/// Register offsets from `FDC_PORT`
+pub const REG_COMMAND: usize = 0; // Reads return status and clear INTRQ
+pub const REG_TRACK: usize = 1;
+pub const REG_SECTOR: usize = 2;
+pub const REG_DATA: usize = 3;
+pub const REG_LATCH: usize = 4;   // Write drive/side select; read LATCH_REQUEST
+
+/// Drives the latch can select
+pub const DRIVES: usize = 4;
+
+/// Drive select latch bits
+pub const LATCH_DRIVE: u8 = 0x03;   // Drive number
+pub const LATCH_SIDE: u8 = 0x04;    // Side 1
+pub const LATCH_DENSITY: u8 = 0x08; // Double density; accepted and ignored
+pub const LATCH_REQUEST: u8 = 0x80; // Read back while INTRQ or DRQ is active
+
+/// Status bits common to all commands
+pub const STATUS_NOT_READY: u8 = 0x80;     // No disk in the selected drive
+pub const STATUS_WRITE_PROTECT: u8 = 0x40;
+pub const STATUS_BUSY: u8 = 0x01;
+/// Status bits after a type I (restore, seek, step) command
+pub const STATUS_HEAD_LOADED: u8 = 0x20;
+pub const STATUS_SEEK_ERROR: u8 = 0x10;
+pub const STATUS_TRACK_0: u8 = 0x04;
+/// Status bits after a type II or III (read, write, format) command
+pub const STATUS_NOT_FOUND: u8 = 0x10;     // Record not found
+pub const STATUS_DRQ: u8 = 0x02;           // The data register wants reading or writing
+
+/// Bytes on a double-density track, written by a Write Track command before it ends
+pub const TRACK_BYTES: usize = 6250;
+
+/// Command flag bits
+const FLAG_HEAD_LOAD: u8 = 0x08;    // Type I: load the head
+const FLAG_UPDATE: u8 = 0x10;       // Step commands: update the track register
+const FLAG_MULTIPLE: u8 = 0x10;     // Type II: carry on to the next sector
+const FLAG_IMMEDIATE: u8 = 0x08;    // Force Interrupt: interrupt now
+
+/// Tracks the head can step through before the mechanism stops it
+const MAX_TRACK: u8 = 83;
+
+/// What the data register is doing for the current command
+enum Transfer {
+    Idle,
+    Read { buffer: Vec<u8>, position: usize, multiple: bool }, // Read Sector and Read Address
+    Write { buffer: Vec<u8>, multiple: bool },                 // Write Sector
+    Format(FormatState),                                       // Write Track
+}
+
+/// Progress through the raw track a Write Track command is given
+struct FormatState {
+    written: usize,         // Track bytes received so far
+    synced: bool,           // The previous byte was an F5 (A1 sync) mark
+    field: Field,
+    id: Option<(u8, usize)>, // Sector number and size of the last ID field
+}
+
+enum Field {
+    Gap,
+    Id(Vec<u8>),              // Track, side, sector and size code after an FE mark
+    Data(u8, usize, Vec<u8>), // Sector number, size and the data after an FB mark
+}
+
+/// WD2793-style floppy disk controller with the MicroBee drive select latch.
+///
+/// Commands complete as soon as their data has been transferred; there are
+/// no rotational or step delays. The head position is the physical track on
+/// the image, and sectors are found by the track register matching it and the
+/// sector register giving the sector number, on the side chosen by the latch.
+/// Write Track (format) takes the raw track stream and writes the data fields
+/// of the sectors it describes; Read Track is not supported and returns no
+/// data. INTRQ drives the IRQ line when one is attached.
+pub struct Fdc {
+    drives: [Option<DiskImage>; DRIVES],
+    heads: [u8; DRIVES], // Physical track under each drive's head
+    latch: u8,
+    status: u8,
+    type_one: bool,      // The last command was type I, which changes what status reports
+    track: u8,
+    sector: u8,
+    data: u8,
+    step_in: bool,       // Direction of the last step
+    transfer: Transfer,
+    intrq: bool,
+    pub irq: Option<IrqLine>,
+}
+
+impl Default for Fdc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fdc {
+    /// Create a controller with empty drives
+    pub fn new() -> Self {
+        Fdc {
+            drives: Default::default(),
+            heads: [0; DRIVES],
+            latch: 0,
+            status: 0,
+            type_one: true,
+            track: 0,
+            sector: 1,
+            data: 0,
+            step_in: true,
+            transfer: Transfer::Idle,
+            intrq: false,
+            irq: None,
+        }
+    }
+
+    /// Put a disk in a drive, returning any disk that was there
+    pub fn insert(&mut self, drive: usize, image: DiskImage) -> Option<DiskImage> {
+        self.drives[drive].replace(image)
+    }
+
+    /// Take the disk out of a drive
+    pub fn eject(&mut self, drive: usize) -> Option<DiskImage> {
+        self.drives[drive].take()
+    }
+
+    /// The disk in a drive
+    pub fn disk(&self, drive: usize) -> Option<&DiskImage> {
+        self.drives[drive].as_ref()
+    }
+
+    /// Save every disk that has been written back to its file
+    pub fn save(&mut self) -> Result<(), String> {
+        self.drives.iter_mut().flatten().try_for_each(DiskImage::save)
+    }
+
+    fn drive(&self) -> usize {
+        (self.latch & LATCH_DRIVE) as usize
+    }
+
+    fn side(&self) -> u8 {
+        (self.latch & LATCH_SIDE != 0) as u8
+    }
+
+    fn head(&self) -> u8 {
+        self.heads[self.drive()]
+    }
+
+    fn selected(&self) -> Option<&DiskImage> {
+        self.drives[self.drive()].as_ref()
+    }
+
+    fn set_intrq(&mut self, active: bool) {
+        self.intrq = active;
+        if let Some(irq) = &self.irq {
+            if active {
+                irq.assert();
+            } else {
+                irq.release();
+            }
+        }
+    }
+
+    /// End the current command with `flags` added to the status and raise INTRQ
+    fn finish(&mut self, flags: u8) {
+        self.transfer = Transfer::Idle;
+        self.status = (self.status & !(STATUS_BUSY | STATUS_DRQ)) | flags;
+        self.set_intrq(true);
+    }
+
+    fn command(&mut self, command: u8) {
+        if command & 0xF0 == 0xD0 {
+            self.force_interrupt(command);
+            return;
+        }
+        if self.status & STATUS_BUSY != 0 {
+            return; // The real controller ignores other commands while busy
+        }
+        self.set_intrq(false);
+        self.transfer = Transfer::Idle;
+        self.type_one = command & 0x80 == 0;
+        self.status = STATUS_BUSY;
+        if self.selected().is_none() {
+            self.finish(STATUS_NOT_READY);
+            return;
+        }
+        match command >> 4 {
+            0x0 => {
+                self.heads[self.drive()] = 0;
+                self.track = 0;
+                self.finish_type_one(command);
+            }
+            0x1 => {
+                let target = self.data;
+                let head = self.head() as i16 + target as i16 - self.track as i16;
+                self.step_in = target > self.track;
+                self.heads[self.drive()] = head.clamp(0, MAX_TRACK as i16) as u8;
+                self.track = target;
+                self.finish_type_one(command);
+            }
+            0x2 | 0x3 => self.step(command, self.step_in),
+            0x4 | 0x5 => self.step(command, true),
+            0x6 | 0x7 => self.step(command, false),
+            0x8 | 0x9 => self.read_sector(command & FLAG_MULTIPLE != 0),
+            0xA | 0xB => self.write_sector(command & FLAG_MULTIPLE != 0),
+            0xC => self.read_address(),
+            0xE => self.finish(0),
+            _ => self.write_track(),
+        }
+    }
+
+    fn force_interrupt(&mut self, command: u8) {
+        let busy = self.status & STATUS_BUSY != 0;
+        self.transfer = Transfer::Idle;
+        self.status &= !(STATUS_BUSY | STATUS_DRQ);
+        if !busy {
+            self.type_one = true;
+        }
+        self.set_intrq(command & FLAG_IMMEDIATE != 0);
+    }
+
+    fn step(&mut self, command: u8, inward: bool) {
+        self.step_in = inward;
+        let drive = self.drive();
+        self.heads[drive] = if inward {
+            (self.heads[drive] + 1).min(MAX_TRACK)
+        } else {
+            self.heads[drive].saturating_sub(1)
+        };
+        if command & FLAG_UPDATE != 0 {
+            self.track = if inward { self.track.wrapping_add(1) } else { self.track.wrapping_sub(1) };
+        }
+        self.finish_type_one(command);
+    }
+
+    fn finish_type_one(&mut self, command: u8) {
+        let mut flags = 0;
+        if command & FLAG_HEAD_LOAD != 0 {
+            flags |= STATUS_HEAD_LOADED;
+        }
+        // Verification reads an ID field, so fails when the track register does not match the head
+        if command & 0x04 != 0 && self.track != self.head() {
+            flags |= STATUS_SEEK_ERROR;
+        }
+        self.finish(flags);
+    }
+
+    /// Contents of the sector the registers select, if it exists
+    fn find_sector(&self) -> Option<Vec<u8>> {
+        if self.track != self.head() {
+            return None;
+        }
+        let sector = self.selected()?.sector(self.head(), self.side(), self.sector)?;
+        Some(sector.to_vec())
+    }
+
+    fn read_sector(&mut self, multiple: bool) {
+        match self.find_sector() {
+            Some(buffer) => {
+                self.status |= STATUS_DRQ;
+                self.transfer = Transfer::Read { buffer, position: 0, multiple };
+            }
+            None => self.finish(STATUS_NOT_FOUND),
+        }
+    }
+
+    fn write_sector(&mut self, multiple: bool) {
+        if self.selected().is_some_and(|disk| disk.write_protected) {
+            self.finish(STATUS_WRITE_PROTECT);
+            return;
+        }
+        match self.find_sector() {
+            Some(sector) => {
+                self.status |= STATUS_DRQ;
+                self.transfer = Transfer::Write { buffer: Vec::with_capacity(sector.len()), multiple };
+            }
+            None => self.finish(STATUS_NOT_FOUND),
+        }
+    }
+
+    fn read_address(&mut self) {
+        let Some(disk) = self.selected() else {
+            self.finish(STATUS_NOT_READY);
+            return;
+        };
+        let geometry = disk.geometry;
+        let head = self.head();
+        if head >= geometry.tracks || self.side() >= geometry.sides {
+            self.finish(STATUS_NOT_FOUND);
+            return;
+        }
+        let mut id = vec![head, self.side(), geometry.first_sector, size_code(geometry.sector_size).unwrap_or(2)];
+        let crc = crc16(&[&[0xA1, 0xA1, 0xA1, 0xFE], &id[..]].concat());
+        id.extend_from_slice(&crc.to_be_bytes());
+        self.sector = head; // The controller copies the track address into the sector register
+        self.status |= STATUS_DRQ;
+        self.transfer = Transfer::Read { buffer: id, position: 0, multiple: false };
+    }
+
+    fn write_track(&mut self) {
+        if self.selected().is_some_and(|disk| disk.write_protected) {
+            self.finish(STATUS_WRITE_PROTECT);
+            return;
+        }
+        self.status |= STATUS_DRQ;
+        self.transfer = Transfer::Format(FormatState { written: 0, synced: false, field: Field::Gap, id: None });
+    }
+
+    fn read_data(&mut self) -> u8 {
+        let Transfer::Read { buffer, position, multiple } = &mut self.transfer else {
+            return self.data;
+        };
+        self.data = buffer[*position];
+        *position += 1;
+        if *position == buffer.len() {
+            if *multiple {
+                self.sector = self.sector.wrapping_add(1);
+                // Running off the end of the track ends the command with STATUS_NOT_FOUND
+                self.read_sector(true);
+            } else {
+                self.finish(0);
+            }
+        }
+        self.data
+    }
+
+    fn write_data(&mut self, value: u8) -> Result<(), String> {
+        self.data = value;
+        let sector_size = self.selected().map_or(0, |disk| disk.geometry.sector_size);
+        match &mut self.transfer {
+            Transfer::Write { buffer, multiple } => {
+                buffer.push(value);
+                if buffer.len() < sector_size {
+                    return Ok(());
+                }
+                let (buffer, multiple) = (std::mem::take(buffer), *multiple);
+                let (head, side, sector) = (self.head(), self.side(), self.sector);
+                if let Some(disk) = self.drives[self.drive()].as_mut() {
+                    disk.write_sector(head, side, sector, &buffer)?;
+                }
+                if multiple {
+                    self.sector = self.sector.wrapping_add(1);
+                    self.write_sector(true);
+                } else {
+                    self.finish(0);
+                }
+            }
+            Transfer::Format(state) => {
+                let formatted = state.accept(value);
+                let done = state.written >= TRACK_BYTES;
+                if let Some((sector, data)) = formatted {
+                    let (head, side) = (self.head(), self.side());
+                    // IDs outside the image's layout cannot be stored in a raw image, so are dropped
+                    if let Some(disk) = self.drives[self.drive()].as_mut()
+                        && disk.sector(head, side, sector).is_some()
+                    {
+                        disk.write_sector(head, side, sector, &data)?;
+                    }
+                }
+                if done {
+                    self.finish(0);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl FormatState {
+    /// Take the next track byte, returning a sector number and its data when a data field ends
+    fn accept(&mut self, value: u8) -> Option<(u8, Vec<u8>)> {
+        self.written += 1;
+        let synced = std::mem::replace(&mut self.synced, value == 0xF5);
+        match &mut self.field {
+            Field::Gap => {
+                if synced && value == 0xFE {
+                    self.field = Field::Id(Vec::with_capacity(4));
+                } else if synced && (value == 0xFB || value == 0xF8)
+                    && let Some((sector, size)) = self.id.take()
+                {
+                    self.field = Field::Data(sector, size, Vec::with_capacity(size));
+                }
+                None
+            }
+            Field::Id(bytes) => {
+                bytes.push(value);
+                if bytes.len() == 4 {
+                    self.id = Some((bytes[2], 128 << (bytes[3] & 0x03)));
+                    self.field = Field::Gap;
+                }
+                None
+            }
+            Field::Data(sector, size, bytes) => {
+                bytes.push(value);
+                if bytes.len() < *size {
+                    return None;
+                }
+                let data = std::mem::take(bytes);
+                let sector = *sector;
+                self.field = Field::Gap;
+                Some((sector, data))
+            }
+        }
+    }
+}
+
+impl Device for Fdc {
+    fn read(&mut self, offset: usize) -> Result<u8, String> {
+        match offset {
+            REG_COMMAND => {
+                let status = self.peek(offset);
+                self.set_intrq(false);
+                Ok(status)
+            }
+            REG_DATA => Ok(self.read_data()),
+            _ => Ok(self.peek(offset)),
+        }
+    }
+
+    fn write(&mut self, offset: usize, value: u8) -> Result<(), String> {
+        match offset {
+            REG_COMMAND => self.command(value),
+            REG_TRACK => self.track = value,
+            REG_SECTOR => self.sector = value,
+            REG_DATA => self.write_data(value)?,
+            REG_LATCH => self.latch = value,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn peek(&self, offset: usize) -> u8 {
+        match offset {
+            REG_COMMAND => {
+                let mut status = self.status;
+                match self.selected() {
+                    None => status |= STATUS_NOT_READY,
+                    Some(disk) if self.type_one => {
+                        if disk.write_protected {
+                            status |= STATUS_WRITE_PROTECT;
+                        }
+                        if self.head() == 0 {
+                            status |= STATUS_TRACK_0;
+                        }
+                    }
+                    Some(_) => {}
+                }
+                status
+            }
+            REG_TRACK => self.track,
+            REG_SECTOR => self.sector,
+            REG_DATA => self.data,
+            REG_LATCH if self.intrq || self.status & STATUS_DRQ != 0 => LATCH_REQUEST,
+            _ => 0,
+        }
+    }
+}
+
+/// CRC-CCITT as the controller computes it over ID and data fields
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0xFFFF, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::Geometry;
+    use crate::interrupt::InterruptController;
+
+    /// A DS40 disk whose every byte depends on its offset, in drive 0
+    fn controller() -> Fdc {
+        let size = Geometry::DS40.size();
+        let data = (0..size).map(|offset| (offset / 512 * 7 + offset) as u8).collect();
+        let mut fdc = Fdc::new();
+        fdc.insert(0, DiskImage::from_bytes(data, None).unwrap());
+        fdc
+    }
+
+    fn status(fdc: &mut Fdc) -> u8 {
+        fdc.read(REG_COMMAND).unwrap()
+    }
+
+    fn seek(fdc: &mut Fdc, track: u8) {
+        fdc.write(REG_DATA, track).unwrap();
+        fdc.write(REG_COMMAND, 0x10).unwrap();
+    }
+
+    /// Read bytes from the data register while the controller asks for them
+    fn read_all(fdc: &mut Fdc) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        while fdc.peek(REG_COMMAND) & STATUS_DRQ != 0 {
+            bytes.push(fdc.read(REG_DATA).unwrap());
+        }
+        bytes
+    }
+
+    #[test]
+    fn restore_and_seek_move_the_head() {
+        let mut fdc = controller();
+        seek(&mut fdc, 10);
+        assert_eq!(fdc.peek(REG_TRACK), 10);
+        assert_eq!(status(&mut fdc) & (STATUS_TRACK_0 | STATUS_BUSY), 0);
+        fdc.write(REG_COMMAND, 0xC0).unwrap(); // Read address reports the track under the head
+        assert_eq!(read_all(&mut fdc)[..4], [10, 0, 1, 2]);
+
+        fdc.write(REG_COMMAND, 0x08).unwrap(); // Restore with head load
+        assert_eq!(fdc.peek(REG_TRACK), 0);
+        assert_eq!(status(&mut fdc), STATUS_TRACK_0 | STATUS_HEAD_LOADED);
+    }
+
+    #[test]
+    fn steps_update_the_track_register_only_when_asked() {
+        let mut fdc = controller();
+        for _ in 0..3 {
+            fdc.write(REG_COMMAND, 0x50).unwrap(); // Step in, update
+        }
+        assert_eq!((fdc.head(), fdc.peek(REG_TRACK)), (3, 3));
+        fdc.write(REG_COMMAND, 0x60).unwrap(); // Step out, no update
+        assert_eq!((fdc.head(), fdc.peek(REG_TRACK)), (2, 3));
+        fdc.write(REG_COMMAND, 0x24).unwrap(); // Step the same way again, verifying
+        assert_eq!((fdc.head(), fdc.peek(REG_TRACK)), (1, 3));
+        assert_eq!(status(&mut fdc) & STATUS_SEEK_ERROR, STATUS_SEEK_ERROR);
+        fdc.write(REG_TRACK, 1).unwrap(); // Resynchronise with the head
+        fdc.write(REG_COMMAND, 0x54).unwrap(); // Step in, update, verify
+        assert_eq!((fdc.head(), fdc.peek(REG_TRACK)), (2, 2));
+        assert_eq!(status(&mut fdc) & STATUS_SEEK_ERROR, 0);
+    }
+
+    #[test]
+    fn head_stops_at_both_ends() {
+        let mut fdc = controller();
+        fdc.write(REG_COMMAND, 0x60).unwrap();
+        assert_eq!(fdc.head(), 0);
+        for _ in 0..100 {
+            fdc.write(REG_COMMAND, 0x40).unwrap();
+        }
+        assert_eq!(fdc.head(), MAX_TRACK);
+    }
+
+    #[test]
+    fn reads_a_sector_from_the_selected_side() {
+        let mut fdc = controller();
+        let irq = InterruptController::new();
+        fdc.irq = Some(irq.line(2));
+        seek(&mut fdc, 2);
+        fdc.write(REG_LATCH, LATCH_SIDE).unwrap();
+        fdc.write(REG_SECTOR, 3).unwrap();
+        fdc.write(REG_COMMAND, 0x80).unwrap();
+        assert_eq!(irq.requests(), 0);
+        assert_eq!(read_all(&mut fdc), fdc.disk(0).unwrap().sector(2, 1, 3).unwrap());
+        assert_eq!(fdc.peek(REG_LATCH), LATCH_REQUEST);
+        assert_eq!(irq.requests(), 1 << 2);
+        assert_eq!(status(&mut fdc), 0);
+        assert_eq!(fdc.peek(REG_LATCH), 0);
+        assert_eq!(irq.requests(), 0);
+    }
+
+    #[test]
+    fn multiple_sector_read_runs_to_the_end_of_the_track() {
+        let mut fdc = controller();
+        fdc.write(REG_SECTOR, 9).unwrap();
+        fdc.write(REG_COMMAND, 0x90).unwrap();
+        let disk = fdc.disk(0).unwrap();
+        let expected = [disk.sector(0, 0, 9).unwrap(), disk.sector(0, 0, 10).unwrap()].concat();
+        assert_eq!(read_all(&mut fdc), expected);
+        assert_eq!(status(&mut fdc), STATUS_NOT_FOUND);
+    }
+
+    #[test]
+    fn writes_a_sector_to_the_image() {
+        let mut fdc = controller();
+        seek(&mut fdc, 1);
+        fdc.write(REG_SECTOR, 4).unwrap();
+        fdc.write(REG_COMMAND, 0xA0).unwrap();
+        let data: Vec<u8> = (0..512).map(|index| (index * 3) as u8).collect();
+        for &byte in &data {
+            assert_eq!(fdc.peek(REG_COMMAND) & STATUS_DRQ, STATUS_DRQ);
+            fdc.write(REG_DATA, byte).unwrap();
+        }
+        assert_eq!(status(&mut fdc), 0);
+        let disk = fdc.disk(0).unwrap();
+        assert!(disk.is_dirty());
+        assert_eq!(disk.sector(1, 0, 4).unwrap(), data);
+        assert_ne!(disk.sector(1, 0, 5).unwrap(), data);
+    }
+
+    #[test]
+    fn missing_sectors_protected_disks_and_empty_drives_fail() {
+        let mut fdc = controller();
+        fdc.write(REG_SECTOR, 11).unwrap();
+        fdc.write(REG_COMMAND, 0x80).unwrap();
+        assert_eq!(status(&mut fdc), STATUS_NOT_FOUND);
+
+        fdc.write(REG_SECTOR, 1).unwrap();
+        fdc.write(REG_TRACK, 5).unwrap(); // Track register disagrees with the head
+        fdc.write(REG_COMMAND, 0x80).unwrap();
+        assert_eq!(status(&mut fdc), STATUS_NOT_FOUND);
+
+        let mut disk = fdc.eject(0).unwrap();
+        disk.write_protected = true;
+        fdc.insert(0, disk);
+        fdc.write(REG_TRACK, 0).unwrap();
+        fdc.write(REG_COMMAND, 0xA0).unwrap();
+        assert_eq!(status(&mut fdc), STATUS_WRITE_PROTECT);
+        assert!(!fdc.disk(0).unwrap().is_dirty());
+
+        fdc.write(REG_LATCH, 1).unwrap();
+        fdc.write(REG_COMMAND, 0x80).unwrap();
+        assert_eq!(status(&mut fdc), STATUS_NOT_READY);
+    }
+}
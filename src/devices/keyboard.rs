@@ -0,0 +1,168 @@
+use std::collections::VecDeque;
+
+use super::Device;
+use crate::interrupt::IrqLine;
+
+/// First of the keyboard's two ports: data at +0, status at +1
+pub const KEYBOARD_PORT: u8 = 0x18;
+
+/// Status register bit set while a key is waiting in the data register
+pub const STATUS_KEY_READY: u8 = 0x01;
+
+/// Latched-ASCII keyboard.
+///
+/// Keys queue up until the program reads them. Reading the data register
+/// returns the oldest key and removes it; reading it with nothing queued
+/// returns 0. When an IRQ line is attached, it is asserted whenever a key is
+/// waiting.
+#[derive(Default)]
+pub struct Keyboard {
+    queue: VecDeque<u8>,
+    script: VecDeque<(u64, u8)>, // Keys to press at a given cycle count, in order
+    cycle: u64,                  // Cycles seen through `tick`
+    pub irq: Option<IrqLine>,    // Raised while a key is waiting
+}
+
+impl Keyboard {
+    /// Create a keyboard with no keys queued and no IRQ
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a key as if it had just been pressed
+    pub fn press(&mut self, key: u8) {
+        self.queue.push_back(key);
+        self.update_irq();
+    }
+
+    /// Queue every byte of `text`
+    pub fn type_text(&mut self, text: &str) {
+        text.bytes().for_each(|key| self.press(key));
+    }
+
+    /// Number of keys waiting to be read
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Whether scripted keys remain to be pressed
+    pub fn script_pending(&self) -> bool {
+        !self.script.is_empty()
+    }
+
+    /// Schedule keys from a script, timing them for a CPU at `clock_hz`.
+    ///
+    /// Each line is `TIME TEXT`: the keys in TEXT are pressed once TIME has
+    /// passed since reset. TIME is in cycles, or in milliseconds or seconds
+    /// with an `ms` or `s` suffix. TEXT starts after the first space and may
+    /// use `\n`, `\r`, `\t`, `\e`, `\\` and `\xHH`. Blank lines and lines
+    /// starting with `#` are ignored.
+    pub fn load_script(&mut self, script: &str, clock_hz: u64) -> Result<(), String> {
+        let mut keys = Vec::new();
+        for (index, line) in script.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (time, text) = line.split_once(' ').unwrap_or((line, ""));
+            let at = parse_time(time, clock_hz).map_err(|err| format!("line {}: {}", index + 1, err))?;
+            let text = unescape(text).map_err(|err| format!("line {}: {}", index + 1, err))?;
+            keys.extend(text.into_iter().map(|key| (at, key)));
+        }
+        self.script.extend(keys);
+        self.script.make_contiguous().sort_by_key(|&(at, _)| at); // Stable, so keys on one line stay in order
+        Ok(())
+    }
+
+    fn update_irq(&self) {
+        if let Some(irq) = &self.irq {
+            if self.queue.is_empty() {
+                irq.release();
+            } else {
+                irq.assert();
+            }
+        }
+    }
+}
+
+impl Device for Keyboard {
+    fn read(&mut self, offset: usize) -> Result<u8, String> {
+        match offset {
+            0 => {
+                let key = self.queue.pop_front().unwrap_or(0);
+                self.update_irq();
+                Ok(key)
+            }
+            _ => Ok(self.peek(offset)),
+        }
+    }
+
+    fn write(&mut self, _offset: usize, _value: u8) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn peek(&self, offset: usize) -> u8 {
+        match offset {
+            0 => self.queue.front().copied().unwrap_or(0),
+            1 if !self.queue.is_empty() => STATUS_KEY_READY,
+            _ => 0,
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.cycle += cycles as u64;
+        while let Some(&(at, key)) = self.script.front() {
+            if at > self.cycle {
+                break;
+            }
+            self.script.pop_front();
+            self.press(key);
+        }
+    }
+}
+
+fn parse_time(text: &str, clock_hz: u64) -> Result<u64, String> {
+    let bad = || format!("bad time '{}'", text);
+    let too_late = || format!("time '{}' is too far in the future", text);
+    if let Some(millis) = text.strip_suffix("ms") {
+        let millis: u64 = millis.parse().map_err(|_| bad())?;
+        millis.checked_mul(clock_hz).map(|cycles| cycles / 1000).ok_or_else(too_late)
+    } else if let Some(seconds) = text.strip_suffix('s') {
+        let seconds: f64 = seconds.parse().map_err(|_| bad())?;
+        if seconds.is_nan() || seconds < 0.0 {
+            return Err(bad());
+        }
+        let cycles = seconds * clock_hz as f64;
+        if cycles >= u64::MAX as f64 {
+            return Err(too_late());
+        }
+        Ok(cycles as u64)
+    } else {
+        text.parse().map_err(|_| bad())
+    }
+}
+
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('t') => bytes.push(b'\t'),
+            Some('e') => bytes.push(0x1B),
+            Some('\\') => bytes.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let value = u8::from_str_radix(&hex, 16).map_err(|_| format!("bad escape '\\x{}'", hex))?;
+                bytes.push(value);
+            }
+            other => return Err(format!("bad escape '\\{}'", other.map(String::from).unwrap_or_default())),
+        }
+    }
+    Ok(bytes)
+}
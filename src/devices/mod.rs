@@ -0,0 +1,59 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::bus::Bus;
+
+pub mod block;    // Paravirtual DMA block device backed by a host image file
+pub mod cassette; // Cassette interface playing and recording bytes at tape speed
+pub mod fdc;      // WD2793-style floppy disk controller and drive latch
+pub mod keyboard; // Latched-ASCII keyboard on two I/O ports
+pub mod rtc;      // MC146818-style real-time clock with alarm and NVRAM
+pub mod speaker;  // 1-bit speaker on a PIO line, recorded for WAV output
+pub mod timer;    // Programmable interval timer raising periodic interrupts
+pub mod uart;     // Serial port with FIFOs and a receive interrupt
+pub mod video;    // 64x16 text display with character ROM and PCG RAM
+
+/// Peripheral attached to the bus.
+///
+/// Offsets are relative to the start of the range the device is mapped at.
+/// A failed access is reported as a message, which the bus turns into
+/// `EmuError::BusFault` with the absolute address.
+pub trait Device {
+    /// Read a register as the CPU would; may have side effects
+    fn read(&mut self, offset: usize) -> Result<u8, String>;
+
+    /// Write a register as the CPU would
+    fn write(&mut self, offset: usize, value: u8) -> Result<(), String>;
+
+    /// Read a register without side effects, for the debugger
+    fn peek(&self, offset: usize) -> u8;
+
+    /// Advance by `cycles` CPU clock cycles
+    fn tick(&mut self, _cycles: u32) {}
+
+    /// Carry out any pending direct memory access; called after `tick`
+    fn dma(&mut self, _memory: &mut dyn Bus) {}
+}
+
+/// Shared devices, so the host can keep a handle to a device it has mapped
+impl<D: Device + ?Sized> Device for Rc<RefCell<D>> {
+    fn read(&mut self, offset: usize) -> Result<u8, String> {
+        self.borrow_mut().read(offset)
+    }
+
+    fn write(&mut self, offset: usize, value: u8) -> Result<(), String> {
+        self.borrow_mut().write(offset, value)
+    }
+
+    fn peek(&self, offset: usize) -> u8 {
+        self.borrow().peek(offset)
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.borrow_mut().tick(cycles)
+    }
+
+    fn dma(&mut self, memory: &mut dyn Bus) {
+        self.borrow_mut().dma(memory)
+    }
+}
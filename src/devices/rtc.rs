@@ -0,0 +1,524 @@
+use std::ops::RangeInclusive;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::Device;
+use crate::interrupt::IrqLine;
+
+/// The MicroBee decodes the RTC's address latch at port 04 and its data port at 07
+pub const RTC_PORT: u8 = 0x04;
+pub const RTC_ADDRESS_OFFSET: usize = 0;
+pub const RTC_DATA_OFFSET: usize = 3;
+
+/// Register numbers, selected by writing the address port
+pub const REG_SECONDS: u8 = 0x00;
+pub const REG_SECONDS_ALARM: u8 = 0x01;
+pub const REG_MINUTES: u8 = 0x02;
+pub const REG_MINUTES_ALARM: u8 = 0x03;
+pub const REG_HOURS: u8 = 0x04;
+pub const REG_HOURS_ALARM: u8 = 0x05;
+pub const REG_DAY_OF_WEEK: u8 = 0x06; // 1 = Sunday
+pub const REG_DAY_OF_MONTH: u8 = 0x07;
+pub const REG_MONTH: u8 = 0x08;
+pub const REG_YEAR: u8 = 0x09; // 70-99 are 1970-1999, 00-69 are 2000-2069
+pub const REG_A: u8 = 0x0A;
+pub const REG_B: u8 = 0x0B;
+pub const REG_C: u8 = 0x0C;
+pub const REG_D: u8 = 0x0D;
+
+/// First battery-backed RAM register and the number of them
+pub const NVRAM_START: u8 = 0x0E;
+pub const NVRAM_SIZE: usize = 50;
+
+/// Register A bits
+pub const A_RATE: u8 = 0x0F; // Periodic interrupt rate select
+
+/// Register B bits
+pub const B_SET: u8 = 0x80;     // Freeze the clock so the time can be written as a whole
+pub const B_PIE: u8 = 0x40;     // Periodic interrupt enable
+pub const B_AIE: u8 = 0x20;     // Alarm interrupt enable
+pub const B_UIE: u8 = 0x10;     // Update-ended interrupt enable
+pub const B_BINARY: u8 = 0x04;  // Time registers are binary rather than BCD
+pub const B_24_HOUR: u8 = 0x02; // 24-hour mode; in 12-hour mode bit 7 of the hours is PM
+
+/// Register C bits, cleared by reading C
+pub const C_IRQF: u8 = 0x80; // Any enabled interrupt flag below is set
+pub const C_PF: u8 = 0x40;   // Periodic
+pub const C_AF: u8 = 0x20;   // Alarm
+pub const C_UF: u8 = 0x10;   // Update ended
+
+/// Register D bit reporting that the battery and RAM are good
+pub const D_VRT: u8 = 0x80;
+
+/// Years the two-digit year register can hold, and the Unix seconds they span
+pub const YEARS: RangeInclusive<i64> = 1970..=2069;
+pub const TIME_RANGE: RangeInclusive<i64> = 0..=3_155_759_999; // To 2069-12-31T23:59:59
+
+/// Alarm register values from C0 upwards match any time
+const ALARM_ANY: u8 = 0xC0;
+
+/// Where the clock gets the current time from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeSource {
+    Host,                 // The host's clock
+    Fixed { start: i64 }, // Starts at `start` (Unix seconds) and advances with emulated cycles
+}
+
+/// Calendar time as the clock registers see it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32, // 1-12
+    pub day: u32,   // 1-31
+    pub hour: u32,  // 0-23
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl DateTime {
+    /// Convert Unix seconds to a UTC calendar time
+    pub fn from_unix(seconds: i64) -> Self {
+        let days = seconds.div_euclid(86_400);
+        let time = seconds.rem_euclid(86_400) as u32;
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            year,
+            month,
+            day,
+            hour: time / 3600,
+            minute: time / 60 % 60,
+            second: time % 60,
+        }
+    }
+
+    /// Convert to Unix seconds; out-of-range fields roll over into the next unit, and
+    /// times beyond what an i64 holds saturate
+    pub fn to_unix(self) -> i64 {
+        let seconds = days_from_civil(self.year, self.month, self.day) * 86_400
+            + self.hour as i128 * 3600
+            + self.minute as i128 * 60
+            + self.second as i128;
+        seconds.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+    }
+
+    /// Parse `YYYY-MM-DDTHH:MM:SS` (a space may replace the `T`) in one of `YEARS`
+    pub fn parse(text: &str) -> Option<Self> {
+        let (date, time) = text.split_once(['T', ' '])?;
+        let mut date = date.splitn(3, '-');
+        let mut time = time.splitn(3, ':').map(|part| part.parse::<u32>().ok());
+        let parsed = DateTime {
+            year: date.next()?.parse().ok().filter(|year| YEARS.contains(year))?,
+            month: date.next()?.parse().ok()?,
+            day: date.next()?.parse().ok()?,
+            hour: time.next()??,
+            minute: time.next()??,
+            second: time.next()??,
+        };
+        (DateTime::from_unix(parsed.to_unix()) == parsed).then_some(parsed)
+    }
+
+    /// Day of the week, 1 = Sunday
+    pub fn day_of_week(self) -> u32 {
+        // 1970-01-01 was a Thursday
+        (days_from_civil(self.year, self.month, self.day) + 4).rem_euclid(7) as u32 + 1
+    }
+}
+
+/// MC146818-style real-time clock with alarm, update and periodic interrupts
+/// and 50 bytes of battery-backed RAM.
+///
+/// The program selects a register through the address port and reads or
+/// writes it through the data port. Time registers follow the BCD/binary and
+/// 12/24-hour modes in register B. Without `B_SET`, writing a time register
+/// changes that field immediately; with it, writes are collected and take
+/// effect together when `B_SET` is cleared.
+pub struct Rtc {
+    source: TimeSource,
+    offset: i64,              // Seconds added to the source's time by programs setting the clock
+    staged: Option<DateTime>, // Time being written while B_SET is set
+    address: u8,
+    alarm: [u8; 3],           // Seconds, minutes and hours alarm registers, as written
+    reg_a: u8,
+    reg_b: u8,
+    flags: u8,                // Register C
+    nvram: [u8; NVRAM_SIZE],
+    nvram_dirty: bool,
+    clock_hz: u64,
+    cycles: u64,              // Cycles since reset, the time base of `TimeSource::Fixed`
+    since_check: u64,         // Cycles since the host clock was last sampled
+    last_second: i64,         // Last time the update cycle ran
+    periodic: u64,            // Cycles towards the next periodic interrupt
+    pub irq: Option<IrqLine>,
+}
+
+impl Rtc {
+    /// Create a clock reading time from `source`, for a CPU at `clock_hz`
+    pub fn new(source: TimeSource, clock_hz: u64) -> Self {
+        let mut rtc = Rtc {
+            source,
+            offset: 0,
+            staged: None,
+            address: 0,
+            alarm: [ALARM_ANY; 3],
+            reg_a: 0x26, // 32.768 kHz time base, 1024 Hz periodic rate
+            reg_b: B_24_HOUR,
+            flags: 0,
+            nvram: [0; NVRAM_SIZE],
+            nvram_dirty: false,
+            clock_hz: clock_hz.max(1),
+            cycles: 0,
+            since_check: 0,
+            last_second: 0,
+            periodic: 0,
+            irq: None,
+        };
+        rtc.last_second = rtc.now();
+        rtc
+    }
+
+    /// Current time in Unix seconds
+    pub fn now(&self) -> i64 {
+        let base = match self.source {
+            TimeSource::Host => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs() as i64),
+            TimeSource::Fixed { start } => {
+                start.saturating_add(i64::try_from(self.cycles / self.clock_hz).unwrap_or(i64::MAX))
+            },
+        };
+        base.saturating_add(self.offset)
+    }
+
+    /// Current calendar time
+    pub fn date_time(&self) -> DateTime {
+        self.staged.unwrap_or_else(|| DateTime::from_unix(self.now()))
+    }
+
+    /// Battery-backed RAM contents
+    pub fn nvram(&self) -> &[u8; NVRAM_SIZE] {
+        &self.nvram
+    }
+
+    /// Restore battery-backed RAM saved by an earlier run; shorter data fills the start
+    pub fn load_nvram(&mut self, bytes: &[u8]) {
+        let count = bytes.len().min(NVRAM_SIZE);
+        self.nvram[..count].copy_from_slice(&bytes[..count]);
+        self.nvram_dirty = false;
+    }
+
+    /// Whether the program has written NVRAM since it was loaded, clearing the flag
+    pub fn take_nvram_dirty(&mut self) -> bool {
+        std::mem::replace(&mut self.nvram_dirty, false)
+    }
+
+    fn encode(&self, value: u32) -> u8 {
+        if self.reg_b & B_BINARY != 0 {
+            value as u8
+        } else {
+            (((value / 10) << 4) | (value % 10)) as u8
+        }
+    }
+
+    fn decode(&self, value: u8) -> u32 {
+        if self.reg_b & B_BINARY != 0 {
+            value as u32
+        } else {
+            (value >> 4) as u32 * 10 + (value & 0x0F) as u32
+        }
+    }
+
+    fn encode_hour(&self, hour: u32) -> u8 {
+        if self.reg_b & B_24_HOUR != 0 {
+            return self.encode(hour);
+        }
+        let pm = if hour >= 12 { 0x80 } else { 0 };
+        let twelve = match hour % 12 {
+            0 => 12,
+            other => other,
+        };
+        self.encode(twelve) | pm
+    }
+
+    fn decode_hour(&self, value: u8) -> u32 {
+        if self.reg_b & B_24_HOUR != 0 {
+            return self.decode(value);
+        }
+        let hour = self.decode(value & 0x7F) % 12;
+        if value & 0x80 != 0 { hour + 12 } else { hour }
+    }
+
+    /// Set the clock so that it reads `time` now
+    fn set_time(&mut self, time: DateTime) {
+        self.offset = self.offset.saturating_add(time.to_unix().saturating_sub(self.now()));
+        self.last_second = self.now();
+    }
+
+    fn write_time_register(&mut self, register: u8, value: u8) {
+        let mut time = self.date_time();
+        match register {
+            REG_SECONDS => time.second = self.decode(value),
+            REG_MINUTES => time.minute = self.decode(value),
+            REG_HOURS => time.hour = self.decode_hour(value),
+            REG_DAY_OF_MONTH => time.day = self.decode(value),
+            REG_MONTH => time.month = self.decode(value),
+            REG_YEAR => {
+                let year = self.decode(value) as i64 % 100;
+                time.year = if year >= 70 { 1900 + year } else { 2000 + year };
+            }
+            _ => return, // Day of week is derived from the date
+        }
+        match &mut self.staged {
+            Some(staged) => *staged = time,
+            None => self.set_time(time),
+        }
+    }
+
+    fn write_reg_b(&mut self, value: u8) {
+        let was_set = self.reg_b & B_SET != 0;
+        if value & B_SET != 0 && !was_set {
+            self.staged = Some(self.date_time());
+        }
+        self.reg_b = value;
+        if value & B_SET == 0
+            && let Some(time) = self.staged.take()
+        {
+            self.set_time(time);
+        }
+        self.update_irq();
+    }
+
+    /// Run the once-a-second update: set the update flag and check the alarm
+    fn update(&mut self, second: i64) {
+        self.flags |= C_UF;
+        let time = DateTime::from_unix(second);
+        let now = [self.encode(time.second), self.encode(time.minute), self.encode_hour(time.hour)];
+        if self.alarm.iter().zip(now).all(|(&alarm, now)| alarm >= ALARM_ANY || alarm == now) {
+            self.flags |= C_AF;
+        }
+    }
+
+    /// Cycles between periodic interrupts for the rate in register A, if any
+    fn periodic_cycles(&self) -> Option<u64> {
+        let rate = (self.reg_a & A_RATE) as u32;
+        let divider = match rate {
+            0 => return None,
+            1 | 2 => 1 << (rate + 6), // Rates 1 and 2 repeat 8 and 9 at these dividers
+            _ => 1 << (rate - 1),
+        };
+        Some((self.clock_hz * divider / 32_768).max(1))
+    }
+
+    fn update_irq(&mut self) {
+        let active = self.flags & self.reg_b & (C_PF | C_AF | C_UF) != 0;
+        if active {
+            self.flags |= C_IRQF;
+        } else {
+            self.flags &= !C_IRQF;
+        }
+        if let Some(irq) = &self.irq {
+            if active {
+                irq.assert();
+            } else {
+                irq.release();
+            }
+        }
+    }
+
+    fn read_register(&self, register: u8) -> u8 {
+        let time = self.date_time();
+        match register {
+            REG_SECONDS => self.encode(time.second),
+            REG_SECONDS_ALARM => self.alarm[0],
+            REG_MINUTES => self.encode(time.minute),
+            REG_MINUTES_ALARM => self.alarm[1],
+            REG_HOURS => self.encode_hour(time.hour),
+            REG_HOURS_ALARM => self.alarm[2],
+            REG_DAY_OF_WEEK => self.encode(time.day_of_week()),
+            REG_DAY_OF_MONTH => self.encode(time.day),
+            REG_MONTH => self.encode(time.month),
+            REG_YEAR => self.encode(time.year.rem_euclid(100) as u32),
+            REG_A => self.reg_a,
+            REG_B => self.reg_b,
+            REG_C => self.flags,
+            REG_D => D_VRT,
+            _ => self.nvram[(register - NVRAM_START) as usize],
+        }
+    }
+}
+
+impl Device for Rtc {
+    fn read(&mut self, offset: usize) -> Result<u8, String> {
+        let value = self.peek(offset);
+        if offset == RTC_DATA_OFFSET && self.address == REG_C {
+            self.flags = 0;
+            self.update_irq();
+        }
+        Ok(value)
+    }
+
+    fn write(&mut self, offset: usize, value: u8) -> Result<(), String> {
+        match offset {
+            RTC_ADDRESS_OFFSET => self.address = value & 0x3F,
+            RTC_DATA_OFFSET => match self.address {
+                REG_SECONDS_ALARM => self.alarm[0] = value,
+                REG_MINUTES_ALARM => self.alarm[1] = value,
+                REG_HOURS_ALARM => self.alarm[2] = value,
+                REG_A => self.reg_a = value & 0x7F, // Update-in-progress is read-only
+                REG_B => self.write_reg_b(value),
+                REG_C | REG_D => {}
+                register if register >= NVRAM_START => {
+                    self.nvram[(register - NVRAM_START) as usize] = value;
+                    self.nvram_dirty = true;
+                }
+                register => self.write_time_register(register, value),
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn peek(&self, offset: usize) -> u8 {
+        match offset {
+            RTC_ADDRESS_OFFSET => self.address,
+            RTC_DATA_OFFSET => self.read_register(self.address),
+            _ => 0xFF,
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.cycles += cycles as u64;
+        if self.reg_b & B_PIE != 0
+            && let Some(period) = self.periodic_cycles()
+        {
+            self.periodic += cycles as u64;
+            if self.periodic >= period {
+                self.periodic %= period;
+                self.flags |= C_PF;
+            }
+        }
+        // The host clock is only sampled a few times per emulated second
+        let check = match self.source {
+            TimeSource::Fixed { .. } => true,
+            TimeSource::Host => {
+                self.since_check += cycles as u64;
+                let due = self.since_check >= self.clock_hz / 64;
+                if due {
+                    self.since_check = 0;
+                }
+                due
+            }
+        };
+        if check && self.staged.is_none() {
+            let now = self.now();
+            // Catch up second by second, but not through a large jump of the host clock
+            let first = self.last_second.saturating_add(1).max(now.saturating_sub(1));
+            for second in first..=now {
+                self.update(second);
+            }
+            self.last_second = self.last_second.max(now);
+        }
+        self.update_irq();
+    }
+}
+
+/// Days since 1970-01-01 to a (year, month, day) date in the proleptic Gregorian calendar
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153; // March = 0
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Inverse of `civil_from_days`; days and months past the end roll over.
+///
+/// Works in i128 so that no year an i64 holds can overflow it.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i128 {
+    // Normalise the month first so e.g. month 13 is January of the next year
+    let months = year as i128 * 12 + month as i128 - 1;
+    let (year, month) = (months.div_euclid(12), months.rem_euclid(12) + 1);
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * shifted_month + 2) / 5 + day as i128 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(year: i64, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> DateTime {
+        DateTime { year, month, day, hour, minute, second }
+    }
+
+    #[test]
+    fn converts_known_dates_both_ways() {
+        let cases = [
+            (0, time(1970, 1, 1, 0, 0, 0)),
+            (951_782_400, time(2000, 2, 29, 0, 0, 0)),
+            (946_684_799, time(1999, 12, 31, 23, 59, 59)),
+            (946_684_800, time(2000, 1, 1, 0, 0, 0)),
+            (1_709_208_000, time(2024, 2, 29, 12, 0, 0)),
+            (*TIME_RANGE.end(), time(2069, 12, 31, 23, 59, 59)),
+        ];
+        for (seconds, date) in cases {
+            assert_eq!(DateTime::from_unix(seconds), date);
+            assert_eq!(date.to_unix(), seconds);
+        }
+    }
+
+    #[test]
+    fn century_and_leap_day_boundaries_follow_on() {
+        assert_eq!(DateTime::from_unix(time(1999, 12, 31, 23, 59, 59).to_unix() + 1), time(2000, 1, 1, 0, 0, 0));
+        assert_eq!(DateTime::from_unix(time(2000, 2, 28, 0, 0, 0).to_unix() + 86_400), time(2000, 2, 29, 0, 0, 0));
+        assert_eq!(DateTime::from_unix(time(2001, 2, 28, 0, 0, 0).to_unix() + 86_400), time(2001, 3, 1, 0, 0, 0));
+        // 2100 is not a leap year
+        assert_eq!(DateTime::from_unix(time(2100, 2, 28, 0, 0, 0).to_unix() + 86_400), time(2100, 3, 1, 0, 0, 0));
+    }
+
+    #[test]
+    fn out_of_range_fields_roll_over() {
+        assert_eq!(time(1999, 13, 1, 0, 0, 0).to_unix(), time(2000, 1, 1, 0, 0, 0).to_unix());
+        assert_eq!(time(2000, 2, 30, 0, 0, 0).to_unix(), time(2000, 3, 1, 0, 0, 0).to_unix());
+    }
+
+    #[test]
+    fn extreme_years_saturate() {
+        assert_eq!(time(i64::MAX, u32::MAX, u32::MAX, 0, 0, 0).to_unix(), i64::MAX);
+        assert_eq!(time(i64::MIN, 1, 1, 0, 0, 0).to_unix(), i64::MIN);
+    }
+
+    #[test]
+    fn day_of_week() {
+        assert_eq!(time(1970, 1, 1, 0, 0, 0).day_of_week(), 5); // Thursday
+        assert_eq!(time(2000, 1, 1, 0, 0, 0).day_of_week(), 7); // Saturday
+        assert_eq!(time(2000, 2, 29, 0, 0, 0).day_of_week(), 3); // Tuesday
+    }
+
+    #[test]
+    fn parses_only_valid_dates_the_clock_can_hold() {
+        assert_eq!(DateTime::parse("2000-02-29T12:34:56"), Some(time(2000, 2, 29, 12, 34, 56)));
+        assert_eq!(DateTime::parse("1999-12-31 23:59:59"), Some(time(1999, 12, 31, 23, 59, 59)));
+        assert_eq!(DateTime::parse("2001-02-29T00:00:00"), None);
+        assert_eq!(DateTime::parse("2000-13-01T00:00:00"), None);
+        assert_eq!(DateTime::parse("2000-01-01T24:00:00"), None);
+        assert_eq!(DateTime::parse("1969-12-31T23:59:59"), None);
+        assert_eq!(DateTime::parse("2070-01-01T00:00:00"), None);
+        assert_eq!(DateTime::parse("9223372036854775807-01-01T00:00:00"), None);
+        assert_eq!(DateTime::parse("2000--1-01T00:00:00"), None);
+    }
+
+    #[test]
+    fn fixed_clock_near_the_end_of_time_does_not_overflow() {
+        let mut rtc = Rtc::new(TimeSource::Fixed { start: i64::MAX }, 1000);
+        rtc.tick(5000);
+        assert_eq!(rtc.now(), i64::MAX);
+    }
+}
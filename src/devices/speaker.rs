@@ -0,0 +1,104 @@
+use std::io::{self, Write};
+
+use super::Device;
+use crate::wav;
+
+/// The MicroBee's PIO port B data register, which drives the speaker
+pub const SPEAKER_PORT: u8 = 0x02;
+
+/// Port B bit wired to the speaker
+pub const SPEAKER_BIT: u8 = 0x40;
+
+/// Sample value while the speaker bit is high; low is silence
+const AMPLITUDE: f64 = 16384.0;
+
+/// 1-bit speaker on a PIO output line.
+///
+/// Every change of the speaker bit is stamped with the cycle count it
+/// happened at, so the sound can be rendered at any sample rate afterwards.
+/// Reads return the last value written to the port.
+pub struct Speaker {
+    clock_hz: u64,
+    latch: u8,        // Last value written to the port
+    edges: Vec<u64>,  // Cycle of every change of the speaker bit, starting from low
+    cycle: u64,       // Cycles seen through `tick`
+}
+
+impl Speaker {
+    /// Create a silent speaker for a CPU at `clock_hz`
+    pub fn new(clock_hz: u64) -> Self {
+        Speaker { clock_hz, latch: 0, edges: Vec::new(), cycle: 0 }
+    }
+
+    /// Whether the speaker bit is currently high
+    pub fn level(&self) -> bool {
+        self.latch & SPEAKER_BIT != 0
+    }
+
+    /// Cycle counts at which the speaker bit changed, starting from low
+    pub fn edges(&self) -> &[u64] {
+        &self.edges
+    }
+
+    /// Render everything up to now as 16-bit samples at `sample_rate`.
+    ///
+    /// Each sample is the fraction of its period the bit spent high, which
+    /// filters out toggling faster than the sample rate can carry.
+    pub fn render(&self, sample_rate: u32) -> Vec<i16> {
+        // Work in units of 1 / (clock_hz * sample_rate) seconds so sample and cycle boundaries are exact
+        let rate = sample_rate as u128;
+        let clock = self.clock_hz as u128;
+        let count = (self.cycle as u128 * rate / clock) as usize;
+        let mut samples = Vec::with_capacity(count);
+        let mut edges = self.edges.iter().map(|&edge| edge as u128 * rate).peekable();
+        let mut high = false;
+        for index in 0..count as u128 {
+            let (start, end) = (index * clock, (index + 1) * clock);
+            let mut time = start;
+            let mut high_time = 0;
+            while let Some(&edge) = edges.peek() {
+                if edge >= end {
+                    break;
+                }
+                if high {
+                    high_time += edge.max(start) - time;
+                }
+                time = edge.max(start);
+                high = !high;
+                edges.next();
+            }
+            if high {
+                high_time += end - time;
+            }
+            samples.push((high_time as f64 / clock as f64 * AMPLITUDE) as i16);
+        }
+        samples
+    }
+
+    /// Write everything up to now as a mono WAV file at `sample_rate`
+    pub fn write_wav(&self, out: impl Write, sample_rate: u32) -> io::Result<()> {
+        wav::write_pcm16(out, sample_rate, &self.render(sample_rate))
+    }
+}
+
+impl Device for Speaker {
+    fn read(&mut self, offset: usize) -> Result<u8, String> {
+        Ok(self.peek(offset))
+    }
+
+    fn write(&mut self, _offset: usize, value: u8) -> Result<(), String> {
+        if (value ^ self.latch) & SPEAKER_BIT != 0 {
+            self.edges.push(self.cycle);
+        }
+        self.latch = value;
+        Ok(())
+    }
+
+    fn peek(&self, _offset: usize) -> u8 {
+        self.latch
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.cycle += cycles as u64;
+    }
+}
@@ -0,0 +1,155 @@
+use super::Device;
+use crate::interrupt::IrqLine;
+
+/// First of the timer's six ports
+pub const TIMER_PORT: u8 = 0x30;
+
+/// Register offsets from `TIMER_PORT`
+pub const REG_RELOAD_LOW: usize = 0;
+pub const REG_RELOAD_HIGH: usize = 1; // Writing it also restarts the count from the reload value
+pub const REG_COUNT_LOW: usize = 2;   // Reading it latches the high byte for REG_COUNT_HIGH
+pub const REG_COUNT_HIGH: usize = 3;
+pub const REG_CONTROL: usize = 4;
+pub const REG_STATUS: usize = 5;      // Reading it clears STATUS_EXPIRED and the IRQ
+
+/// Control register bits
+pub const CONTROL_ENABLE: u8 = 0x01;     // Count down
+pub const CONTROL_PERIODIC: u8 = 0x02;   // Reload and keep counting on expiry instead of stopping
+pub const CONTROL_IRQ: u8 = 0x04;        // Assert the IRQ line on expiry
+pub const CONTROL_PRESCALE: u8 = 0x30;   // Bits 5-4 select 1, 16, 256 or 4096 cycles per count
+
+/// Status register bits
+pub const STATUS_EXPIRED: u8 = 0x01; // The count has reached zero since status was last read
+
+/// Programmable interval timer: a 16-bit down counter clocked by CPU cycles.
+///
+/// When the count reaches zero the timer sets `STATUS_EXPIRED`, raises its IRQ
+/// if enabled, and either reloads (periodic mode) or stops. A reload value of
+/// 0 counts 65536.
+#[derive(Default)]
+pub struct Timer {
+    reload: u16,
+    count: u16,
+    control: u8,
+    expired: bool,
+    latched_high: u8, // High byte of the count captured when the low byte was read
+    residue: u64,     // Cycles towards the next count
+    pub irq: Option<IrqLine>,
+}
+
+impl Timer {
+    /// Create a stopped timer with no IRQ attached
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current count
+    pub fn count(&self) -> u16 {
+        self.count
+    }
+
+    /// Whether the timer is counting
+    pub fn is_running(&self) -> bool {
+        self.control & CONTROL_ENABLE != 0
+    }
+
+    /// CPU cycles per count for the current prescaler setting
+    pub fn prescale(&self) -> u64 {
+        1 << (4 * ((self.control & CONTROL_PRESCALE) >> 4))
+    }
+
+    /// Counts from the reload value to zero
+    fn period(&self) -> u64 {
+        if self.reload == 0 { 0x10000 } else { self.reload as u64 }
+    }
+
+    fn restart(&mut self) {
+        self.count = self.reload;
+        self.residue = 0;
+    }
+
+    fn expire(&mut self) {
+        self.expired = true;
+        if self.control & CONTROL_IRQ != 0
+            && let Some(irq) = &self.irq
+        {
+            irq.assert();
+        }
+        if self.control & CONTROL_PERIODIC == 0 {
+            self.control &= !CONTROL_ENABLE;
+        }
+    }
+}
+
+impl Device for Timer {
+    fn read(&mut self, offset: usize) -> Result<u8, String> {
+        match offset {
+            REG_COUNT_LOW => {
+                self.latched_high = (self.count >> 8) as u8;
+                Ok(self.count as u8)
+            }
+            REG_COUNT_HIGH => Ok(self.latched_high),
+            REG_STATUS => {
+                let status = self.peek(offset);
+                self.expired = false;
+                if let Some(irq) = &self.irq {
+                    irq.release();
+                }
+                Ok(status)
+            }
+            _ => Ok(self.peek(offset)),
+        }
+    }
+
+    fn write(&mut self, offset: usize, value: u8) -> Result<(), String> {
+        match offset {
+            REG_RELOAD_LOW => self.reload = (self.reload & 0xFF00) | value as u16,
+            REG_RELOAD_HIGH => {
+                self.reload = (self.reload & 0x00FF) | ((value as u16) << 8);
+                self.restart();
+            }
+            REG_CONTROL => {
+                let starting = value & CONTROL_ENABLE != 0 && !self.is_running();
+                self.control = value;
+                if starting {
+                    self.restart();
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn peek(&self, offset: usize) -> u8 {
+        match offset {
+            REG_RELOAD_LOW => self.reload as u8,
+            REG_RELOAD_HIGH => (self.reload >> 8) as u8,
+            REG_COUNT_LOW => self.count as u8,
+            REG_COUNT_HIGH => (self.count >> 8) as u8,
+            REG_CONTROL => self.control,
+            REG_STATUS if self.expired => STATUS_EXPIRED,
+            _ => 0,
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        if !self.is_running() {
+            return;
+        }
+        self.residue += cycles as u64;
+        let mut counts = self.residue / self.prescale();
+        self.residue %= self.prescale();
+        while counts > 0 && self.is_running() {
+            // A count of 0 right after a reload of 0 still has the full 65536 to go
+            let remaining = if self.count == 0 { self.period() } else { self.count as u64 };
+            if counts < remaining {
+                self.count = (remaining - counts) as u16;
+                break;
+            }
+            counts -= remaining;
+            self.expire();
+            // Periodic mode carries on from the reload value; one-shot mode stops at 0
+            self.count = if self.is_running() { self.reload } else { 0 };
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::VecDeque;
+
+use super::Device;
+use crate::interrupt::IrqLine;
+
+/// First of the UART's three ports: data at +0, status at +1, control at +2
+pub const UART_PORT: u8 = 0x20;
+
+/// Bytes each FIFO holds
+pub const FIFO_SIZE: usize = 16;
+
+/// Status register bits
+pub const STATUS_RX_READY: u8 = 0x01;   // A received byte is waiting in the data register
+pub const STATUS_TX_READY: u8 = 0x02;   // The transmit FIFO has room for another byte
+pub const STATUS_RX_OVERRUN: u8 = 0x04; // A byte arrived with the receive FIFO full; cleared by reading status
+pub const STATUS_TX_EMPTY: u8 = 0x08;   // Everything written has been sent
+
+/// Control register bits
+pub const CONTROL_RX_IRQ: u8 = 0x01; // Assert the IRQ line while received data is waiting
+
+/// Serial port with 16-byte receive and transmit FIFOs.
+///
+/// Writing the data register queues a byte for transmission; the host
+/// collects sent bytes with `take_output`. Bytes from the host arrive through
+/// `receive` and are read from the data register, which returns 0 when empty.
+/// Transmission takes one byte time per byte at the configured baud rate, or
+/// is immediate when no rate is set.
+pub struct Uart {
+    rx: VecDeque<u8>,
+    tx: VecDeque<u8>,
+    sent: Vec<u8>,            // Transmitted bytes not yet collected by the host
+    overrun: bool,
+    pub control: u8,          // CONTROL_* bits
+    pub irq: Option<IrqLine>, // Raised for received data when CONTROL_RX_IRQ is set
+    cycles_per_byte: u64,     // 0 transmits immediately
+    cycles: u64,              // Cycles towards the next byte leaving the TX FIFO
+}
+
+impl Default for Uart {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Uart {
+    /// Create a UART with empty FIFOs, interrupts off and instant transmission
+    pub fn new() -> Self {
+        Uart {
+            rx: VecDeque::with_capacity(FIFO_SIZE),
+            tx: VecDeque::with_capacity(FIFO_SIZE),
+            sent: Vec::new(),
+            overrun: false,
+            control: 0,
+            irq: None,
+            cycles_per_byte: 0,
+            cycles: 0,
+        }
+    }
+
+    /// Pace transmission at `baud` (10 bits per byte) for a CPU at `clock_hz`; 0 is instant
+    pub fn set_baud(&mut self, baud: u64, clock_hz: u64) {
+        self.cycles_per_byte = (clock_hz * 10).checked_div(baud).unwrap_or(0);
+    }
+
+    /// Deliver a byte from the host; returns false, flagging an overrun, if the receive FIFO is full
+    pub fn receive(&mut self, byte: u8) -> bool {
+        if self.rx.len() >= FIFO_SIZE {
+            self.overrun = true;
+            return false;
+        }
+        self.rx.push_back(byte);
+        self.update_irq();
+        true
+    }
+
+    /// Free space in the receive FIFO, for hosts that pace their input
+    pub fn rx_space(&self) -> usize {
+        FIFO_SIZE - self.rx.len()
+    }
+
+    /// Collect the bytes transmitted since the last call
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.sent)
+    }
+
+    /// Finish transmitting everything in the transmit FIFO at once, e.g. when emulation stops
+    pub fn flush(&mut self) {
+        self.sent.extend(self.tx.drain(..));
+        self.cycles = 0;
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = 0;
+        if !self.rx.is_empty() {
+            status |= STATUS_RX_READY;
+        }
+        if self.tx.len() < FIFO_SIZE {
+            status |= STATUS_TX_READY;
+        }
+        if self.overrun {
+            status |= STATUS_RX_OVERRUN;
+        }
+        if self.tx.is_empty() {
+            status |= STATUS_TX_EMPTY;
+        }
+        status
+    }
+
+    fn update_irq(&self) {
+        if let Some(irq) = &self.irq {
+            if self.control & CONTROL_RX_IRQ != 0 && !self.rx.is_empty() {
+                irq.assert();
+            } else {
+                irq.release();
+            }
+        }
+    }
+}
+
+impl Device for Uart {
+    fn read(&mut self, offset: usize) -> Result<u8, String> {
+        match offset {
+            0 => {
+                let byte = self.rx.pop_front().unwrap_or(0);
+                self.update_irq();
+                Ok(byte)
+            }
+            1 => {
+                let status = self.status();
+                self.overrun = false;
+                Ok(status)
+            }
+            _ => Ok(self.peek(offset)),
+        }
+    }
+
+    fn write(&mut self, offset: usize, value: u8) -> Result<(), String> {
+        match offset {
+            0 => {
+                // Like real hardware, a write to a full FIFO is lost
+                if self.tx.len() < FIFO_SIZE {
+                    self.tx.push_back(value);
+                }
+                if self.cycles_per_byte == 0 {
+                    self.sent.extend(self.tx.drain(..));
+                }
+            }
+            2 => {
+                self.control = value;
+                self.update_irq();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn peek(&self, offset: usize) -> u8 {
+        match offset {
+            0 => self.rx.front().copied().unwrap_or(0),
+            1 => self.status(),
+            2 => self.control,
+            _ => 0,
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        if self.cycles_per_byte == 0 || self.tx.is_empty() {
+            self.cycles = 0;
+            return;
+        }
+        self.cycles += cycles as u64;
+        while self.cycles >= self.cycles_per_byte {
+            self.cycles -= self.cycles_per_byte;
+            match self.tx.pop_front() {
+                Some(byte) => self.sent.push(byte),
+                None => {
+                    self.cycles = 0;
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,366 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use super::Device;
+use crate::png;
+
+/// Characters per row and rows per screen in 64x16 mode
+pub const COLUMNS: usize = 64;
+pub const ROWS: usize = 16;
+
+/// Pixel size of one character cell
+pub const CELL_WIDTH: usize = 8;
+pub const CELL_HEIGHT: usize = 16;
+
+/// Where the MicroBee decodes screen RAM (F000-F7FF) and PCG RAM (F800-FFFF)
+pub const MICROBEE_VIDEO_BASE: usize = 0xF000;
+
+/// Bytes of screen RAM; 64x16 uses the first 1K
+pub const SCREEN_RAM_SIZE: usize = 0x800;
+
+/// Bytes of programmable character generator RAM: 128 characters of 16 rows
+pub const PCG_RAM_SIZE: usize = 0x800;
+
+/// Bytes of character ROM: 128 characters of 16 rows
+pub const CHAR_ROM_SIZE: usize = 0x800;
+
+/// Address range the device occupies from its base
+pub const VIDEO_RANGE_SIZE: usize = SCREEN_RAM_SIZE + PCG_RAM_SIZE;
+
+/// Frame rate of the MicroBee's PAL video
+pub const FRAME_RATE_HZ: u64 = 50;
+
+/// Colours used when rasterising: green phosphor on black
+pub const FOREGROUND: [u8; 3] = [0x33, 0xFF, 0x66];
+pub const BACKGROUND: [u8; 3] = [0x00, 0x00, 0x00];
+
+/// File formats a frame can be exported as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Ppm,
+    Png,
+}
+
+impl ImageFormat {
+    /// Format named by a file extension, e.g. `png`
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "ppm" => Some(ImageFormat::Ppm),
+            "png" => Some(ImageFormat::Png),
+            _ => None,
+        }
+    }
+
+    /// Format implied by the extension of `path`
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(Self::from_extension)
+    }
+
+    /// File extension for the format
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Ppm => "ppm",
+            ImageFormat::Png => "png",
+        }
+    }
+}
+
+/// A rasterised frame, RGB, row-major from the top-left corner
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>, // Three bytes per pixel
+}
+
+impl Frame {
+    /// Write the frame as a binary (P6) PPM image
+    pub fn write_ppm(&self, mut out: impl Write) -> io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+        out.write_all(&self.pixels)
+    }
+
+    /// Write the frame as an RGB PNG image
+    pub fn write_png(&self, out: impl Write) -> io::Result<()> {
+        png::write_rgb(out, self.width, self.height, &self.pixels)
+    }
+
+    /// Write the frame in `format`
+    pub fn write(&self, format: ImageFormat, out: impl Write) -> io::Result<()> {
+        match format {
+            ImageFormat::Ppm => self.write_ppm(out),
+            ImageFormat::Png => self.write_png(out),
+        }
+    }
+}
+
+/// 64x16 text display: screen RAM holding character codes, a character ROM
+/// for codes 00-7F and PCG RAM defining codes 80-FF.
+///
+/// Mapped as one device: screen RAM at offset 0, PCG RAM at `SCREEN_RAM_SIZE`.
+pub struct Video {
+    screen: Vec<u8>,
+    pcg: Vec<u8>,
+    char_rom: Vec<u8>,
+    frame_cycles: u64, // CPU cycles per displayed frame
+    cycles: u64,       // Cycles into the current frame
+    frames: u64,       // Frames completed since reset
+}
+
+impl Video {
+    /// Create a display using the built-in character ROM, timed for a CPU at `clock_hz`
+    pub fn new(clock_hz: u64) -> Self {
+        Video {
+            screen: vec![b' '; SCREEN_RAM_SIZE],
+            pcg: vec![0; PCG_RAM_SIZE],
+            char_rom: builtin_char_rom(),
+            frame_cycles: (clock_hz / FRAME_RATE_HZ).max(1),
+            cycles: 0,
+            frames: 0,
+        }
+    }
+
+    /// Replace the character ROM with a 2K dump of a real one
+    pub fn load_char_rom(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if bytes.len() != CHAR_ROM_SIZE {
+            return Err(format!(
+                "character ROM must be {} bytes, got {}",
+                CHAR_ROM_SIZE,
+                bytes.len()
+            ));
+        }
+        self.char_rom.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Character code displayed at `column`, `row`
+    pub fn char_at(&self, column: usize, row: usize) -> u8 {
+        self.screen[row * COLUMNS + column]
+    }
+
+    /// Number of frames completed since reset
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    /// Pixel row `line` (0 at the top) of character `code`; bit 7 is the leftmost pixel
+    pub fn glyph_row(&self, code: u8, line: usize) -> u8 {
+        let index = (code & 0x7F) as usize * CELL_HEIGHT + line;
+        if code & 0x80 != 0 {
+            self.pcg[index]
+        } else {
+            self.char_rom[index]
+        }
+    }
+
+    /// The screen as plain text, one line per row.
+    ///
+    /// Printable ASCII is shown as is, other ROM characters as `.` and PCG characters as `#`.
+    pub fn text(&self) -> String {
+        self.rows(|code| match code {
+            0x20..=0x7E => code as char,
+            0x80..=0xFF => '#',
+            _ => '.',
+        })
+        .join("\n")
+    }
+
+    /// The screen as ANSI escape codes that home the cursor and redraw it in place.
+    ///
+    /// Graphics characters are drawn as shaded blocks according to how many of their
+    /// pixels are lit.
+    pub fn render_ansi(&self) -> String {
+        let mut out = String::from("\x1b[H\x1b[32m");
+        for line in self.rows(|code| match code {
+            0x20..=0x7E => code as char,
+            _ => self.shade(code),
+        }) {
+            out.push_str(&line);
+            out.push_str("\x1b[K\r\n");
+        }
+        out.push_str("\x1b[0m");
+        out
+    }
+
+    /// Draw the screen into a pixel buffer
+    pub fn rasterise(&self) -> Frame {
+        let (width, height) = (COLUMNS * CELL_WIDTH, ROWS * CELL_HEIGHT);
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            for column in 0..COLUMNS {
+                let bits = self.glyph_row(self.char_at(column, y / CELL_HEIGHT), y % CELL_HEIGHT);
+                for x in 0..CELL_WIDTH {
+                    let lit = bits & (0x80 >> x) != 0;
+                    pixels.extend_from_slice(if lit { &FOREGROUND } else { &BACKGROUND });
+                }
+            }
+        }
+        Frame { width, height, pixels }
+    }
+
+    fn rows(&self, mut show: impl FnMut(u8) -> char) -> Vec<String> {
+        (0..ROWS)
+            .map(|row| (0..COLUMNS).map(|column| show(self.char_at(column, row))).collect())
+            .collect()
+    }
+
+    /// Block character approximating the ink coverage of a glyph
+    fn shade(&self, code: u8) -> char {
+        let lit: u32 = (0..CELL_HEIGHT).map(|line| self.glyph_row(code, line).count_ones()).sum();
+        match lit * 4 / (CELL_WIDTH * CELL_HEIGHT) as u32 {
+            0 if lit == 0 => ' ',
+            0 => '░',
+            1 => '▒',
+            2 => '▓',
+            _ => '█',
+        }
+    }
+}
+
+impl Device for Video {
+    fn read(&mut self, offset: usize) -> Result<u8, String> {
+        Ok(self.peek(offset))
+    }
+
+    fn write(&mut self, offset: usize, value: u8) -> Result<(), String> {
+        match offset {
+            0..SCREEN_RAM_SIZE => self.screen[offset] = value,
+            _ => self.pcg[offset - SCREEN_RAM_SIZE] = value,
+        }
+        Ok(())
+    }
+
+    fn peek(&self, offset: usize) -> u8 {
+        match offset {
+            0..SCREEN_RAM_SIZE => self.screen[offset],
+            _ => self.pcg[offset - SCREEN_RAM_SIZE],
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.cycles += cycles as u64;
+        self.frames += self.cycles / self.frame_cycles;
+        self.cycles %= self.frame_cycles;
+    }
+}
+
+/// Character ROM built from `FONT_5X7`, each glyph placed in the upper middle of its 8x16 cell
+fn builtin_char_rom() -> Vec<u8> {
+    let mut rom = vec![0; CHAR_ROM_SIZE];
+    for (index, columns) in FONT_5X7.iter().enumerate() {
+        let base = (0x20 + index) * CELL_HEIGHT;
+        for line in 0..7 {
+            let mut bits = 0;
+            for (x, column) in columns.iter().enumerate() {
+                if column & (1 << line) != 0 {
+                    bits |= 0x40 >> x;
+                }
+            }
+            // Double each font row so glyphs fill the tall cell, leaving a gap above and below
+            rom[base + 1 + line * 2] = bits;
+            rom[base + 2 + line * 2] = bits;
+        }
+    }
+    rom
+}
+
+/// Classic 5x7 font for 20-7E, five columns per glyph, bit 0 at the top
+const FONT_5X7: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x5F, 0x00, 0x00], // !
+    [0x00, 0x07, 0x00, 0x07, 0x00], // "
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], // #
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12], // $
+    [0x23, 0x13, 0x08, 0x64, 0x62], // %
+    [0x36, 0x49, 0x55, 0x22, 0x50], // &
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '
+    [0x00, 0x1C, 0x22, 0x41, 0x00], // (
+    [0x00, 0x41, 0x22, 0x1C, 0x00], // )
+    [0x08, 0x2A, 0x1C, 0x2A, 0x08], // *
+    [0x08, 0x08, 0x3E, 0x08, 0x08], // +
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ,
+    [0x08, 0x08, 0x08, 0x08, 0x08], // -
+    [0x00, 0x60, 0x60, 0x00, 0x00], // .
+    [0x20, 0x10, 0x08, 0x04, 0x02], // /
+    [0x3E, 0x51, 0x49, 0x45, 0x3E], // 0
+    [0x00, 0x42, 0x7F, 0x40, 0x00], // 1
+    [0x42, 0x61, 0x51, 0x49, 0x46], // 2
+    [0x21, 0x41, 0x45, 0x4B, 0x31], // 3
+    [0x18, 0x14, 0x12, 0x7F, 0x10], // 4
+    [0x27, 0x45, 0x45, 0x45, 0x39], // 5
+    [0x3C, 0x4A, 0x49, 0x49, 0x30], // 6
+    [0x01, 0x71, 0x09, 0x05, 0x03], // 7
+    [0x36, 0x49, 0x49, 0x49, 0x36], // 8
+    [0x06, 0x49, 0x49, 0x29, 0x1E], // 9
+    [0x00, 0x36, 0x36, 0x00, 0x00], // :
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ;
+    [0x08, 0x14, 0x22, 0x41, 0x00], // <
+    [0x14, 0x14, 0x14, 0x14, 0x14], // =
+    [0x00, 0x41, 0x22, 0x14, 0x08], // >
+    [0x02, 0x01, 0x51, 0x09, 0x06], // ?
+    [0x32, 0x49, 0x79, 0x41, 0x3E], // @
+    [0x7E, 0x11, 0x11, 0x11, 0x7E], // A
+    [0x7F, 0x49, 0x49, 0x49, 0x36], // B
+    [0x3E, 0x41, 0x41, 0x41, 0x22], // C
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], // D
+    [0x7F, 0x49, 0x49, 0x49, 0x41], // E
+    [0x7F, 0x09, 0x09, 0x09, 0x01], // F
+    [0x3E, 0x41, 0x49, 0x49, 0x7A], // G
+    [0x7F, 0x08, 0x08, 0x08, 0x7F], // H
+    [0x00, 0x41, 0x7F, 0x41, 0x00], // I
+    [0x20, 0x40, 0x41, 0x3F, 0x01], // J
+    [0x7F, 0x08, 0x14, 0x22, 0x41], // K
+    [0x7F, 0x40, 0x40, 0x40, 0x40], // L
+    [0x7F, 0x02, 0x0C, 0x02, 0x7F], // M
+    [0x7F, 0x04, 0x08, 0x10, 0x7F], // N
+    [0x3E, 0x41, 0x41, 0x41, 0x3E], // O
+    [0x7F, 0x09, 0x09, 0x09, 0x06], // P
+    [0x3E, 0x41, 0x51, 0x21, 0x5E], // Q
+    [0x7F, 0x09, 0x19, 0x29, 0x46], // R
+    [0x46, 0x49, 0x49, 0x49, 0x31], // S
+    [0x01, 0x01, 0x7F, 0x01, 0x01], // T
+    [0x3F, 0x40, 0x40, 0x40, 0x3F], // U
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], // V
+    [0x3F, 0x40, 0x38, 0x40, 0x3F], // W
+    [0x63, 0x14, 0x08, 0x14, 0x63], // X
+    [0x07, 0x08, 0x70, 0x08, 0x07], // Y
+    [0x61, 0x51, 0x49, 0x45, 0x43], // Z
+    [0x00, 0x7F, 0x41, 0x41, 0x00], // [
+    [0x02, 0x04, 0x08, 0x10, 0x20], // \
+    [0x00, 0x41, 0x41, 0x7F, 0x00], // ]
+    [0x04, 0x02, 0x01, 0x02, 0x04], // ^
+    [0x40, 0x40, 0x40, 0x40, 0x40], // _
+    [0x00, 0x01, 0x02, 0x04, 0x00], // `
+    [0x20, 0x54, 0x54, 0x54, 0x78], // a
+    [0x7F, 0x48, 0x44, 0x44, 0x38], // b
+    [0x38, 0x44, 0x44, 0x44, 0x20], // c
+    [0x38, 0x44, 0x44, 0x48, 0x7F], // d
+    [0x38, 0x54, 0x54, 0x54, 0x18], // e
+    [0x08, 0x7E, 0x09, 0x01, 0x02], // f
+    [0x0C, 0x52, 0x52, 0x52, 0x3E], // g
+    [0x7F, 0x08, 0x04, 0x04, 0x78], // h
+    [0x00, 0x44, 0x7D, 0x40, 0x00], // i
+    [0x20, 0x40, 0x44, 0x3D, 0x00], // j
+    [0x7F, 0x10, 0x28, 0x44, 0x00], // k
+    [0x00, 0x41, 0x7F, 0x40, 0x00], // l
+    [0x7C, 0x04, 0x18, 0x04, 0x78], // m
+    [0x7C, 0x08, 0x04, 0x04, 0x78], // n
+    [0x38, 0x44, 0x44, 0x44, 0x38], // o
+    [0x7C, 0x14, 0x14, 0x14, 0x08], // p
+    [0x08, 0x14, 0x14, 0x18, 0x7C], // q
+    [0x7C, 0x08, 0x04, 0x04, 0x08], // r
+    [0x48, 0x54, 0x54, 0x54, 0x20], // s
+    [0x04, 0x3F, 0x44, 0x40, 0x20], // t
+    [0x3C, 0x40, 0x40, 0x20, 0x7C], // u
+    [0x1C, 0x20, 0x40, 0x20, 0x1C], // v
+    [0x3C, 0x40, 0x30, 0x40, 0x3C], // w
+    [0x44, 0x28, 0x10, 0x28, 0x44], // x
+    [0x0C, 0x50, 0x50, 0x50, 0x3C], // y
+    [0x44, 0x64, 0x54, 0x4C, 0x44], // z
+    [0x00, 0x08, 0x36, 0x41, 0x00], // {
+    [0x00, 0x00, 0x7F, 0x00, 0x00], // |
+    [0x00, 0x41, 0x36, 0x08, 0x00], // }
+    [0x08, 0x04, 0x08, 0x10, 0x08], // ~
+];
@@ -0,0 +1,73 @@
+use std::fmt;
+use std::ops::Range;
+
+use crate::bus::Bus;
+use crate::error::EmuError;
+use crate::opcodes::{self, Operand};
+
+/// One decoded instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u16,   // Address of the opcode byte
+    pub bytes: Vec<u8>, // Opcode followed by its operand bytes
+    pub text: String,   // Mnemonic and operand in assembler syntax
+}
+
+impl Instruction {
+    /// Address of the instruction that follows this one
+    pub fn next_address(&self) -> u16 {
+        self.address.wrapping_add(self.bytes.len() as u16)
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(f, "{:04X}  {:<9} {}", self.address, bytes.join(" "), self.text)
+    }
+}
+
+/// Decode the instruction at `address`.
+///
+/// Bytes that are not a known opcode, or whose operand would run past the end
+/// of memory, decode as a one byte `.byte` so the output can be reassembled.
+pub fn decode<B: Bus + ?Sized>(memory: &B, address: u16) -> Result<Instruction, EmuError> {
+    let opcode = memory.peek(address as usize)?;
+    let data_byte = || Instruction {
+        address,
+        bytes: vec![opcode],
+        text: format!(".byte ${:02X}", opcode),
+    };
+    let Some(info) = opcodes::lookup(opcode) else {
+        return Ok(data_byte());
+    };
+
+    let mut bytes = vec![opcode];
+    for offset in 1..info.size() {
+        match memory.peek(address as usize + offset) {
+            Ok(byte) => bytes.push(byte),
+            Err(_) => return Ok(data_byte()),
+        }
+    }
+    let text = match info.operand {
+        Operand::None => info.mnemonic.to_string(),
+        Operand::Immediate => format!("{} #${:02X}", info.mnemonic, bytes[1]),
+        Operand::Address => {
+            let target = u16::from_le_bytes([bytes[1], bytes[2]]);
+            format!("{} ${:04X}", info.mnemonic, target)
+        }
+    };
+    Ok(Instruction { address, bytes, text })
+}
+
+/// Decode every instruction that starts inside `range`
+pub fn disassemble<B: Bus + ?Sized>(memory: &B, range: Range<usize>) -> Result<Vec<Instruction>, EmuError> {
+    let mut listing = Vec::new();
+    let mut address = range.start;
+    while address < range.end.min(memory.size()) {
+        let instruction = decode(memory, address as u16)?;
+        address += instruction.bytes.len();
+        listing.push(instruction);
+    }
+    Ok(listing)
+}
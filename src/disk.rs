@@ -0,0 +1,176 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Byte a freshly formatted sector is filled with
+pub const FORMAT_FILLER: u8 = 0xE5;
+
+/// Layout of a raw sector image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Geometry {
+    pub tracks: u8,
+    pub sides: u8,
+    pub sectors: u8,      // Sectors per track
+    pub sector_size: usize,
+    pub first_sector: u8, // Number of the first sector on each track
+}
+
+impl Geometry {
+    /// MicroBee double-sided 40-track disk: 10 x 512-byte sectors, 400K
+    pub const DS40: Geometry = Geometry { tracks: 40, sides: 2, sectors: 10, sector_size: 512, first_sector: 1 };
+    /// MicroBee single-sided 80-track disk: 10 x 512-byte sectors, 400K
+    pub const SS80: Geometry = Geometry { tracks: 80, sides: 1, sectors: 10, sector_size: 512, first_sector: 1 };
+    /// MicroBee double-sided 80-track disk: 10 x 512-byte sectors, 800K
+    pub const DS80: Geometry = Geometry { tracks: 80, sides: 2, sectors: 10, sector_size: 512, first_sector: 1 };
+
+    /// Bytes in an image with this geometry
+    pub fn size(&self) -> usize {
+        self.tracks as usize * self.sides as usize * self.sectors as usize * self.sector_size
+    }
+
+    /// Guess the geometry of a raw image from its size; 400K images are taken as DS40
+    pub fn from_size(size: usize) -> Option<Geometry> {
+        [Geometry::DS40, Geometry::DS80].into_iter().find(|geometry| geometry.size() == size)
+    }
+
+    /// Parse `ds40`, `ss80`, `ds80` or `TRACKSxSIDESxSECTORSxSIZE`, e.g. `80x2x10x512`
+    pub fn parse(text: &str) -> Option<Geometry> {
+        match text.to_ascii_lowercase().as_str() {
+            "ds40" => return Some(Geometry::DS40),
+            "ss80" => return Some(Geometry::SS80),
+            "ds80" => return Some(Geometry::DS80),
+            _ => {}
+        }
+        let fields: Vec<usize> = text.split('x').map(|field| field.parse().ok()).collect::<Option<_>>()?;
+        let [tracks, sides, sectors, sector_size] = fields[..] else {
+            return None;
+        };
+        let geometry = Geometry {
+            tracks: u8::try_from(tracks).ok()?,
+            sides: u8::try_from(sides).ok()?,
+            sectors: u8::try_from(sectors).ok()?,
+            sector_size,
+            first_sector: 1,
+        };
+        let valid = geometry.tracks > 0
+            && (1..=2).contains(&geometry.sides)
+            && geometry.sectors > 0
+            && size_code(sector_size).is_some();
+        valid.then_some(geometry)
+    }
+}
+
+impl fmt::Display for Geometry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}x{}x{}", self.tracks, self.sides, self.sectors, self.sector_size)
+    }
+}
+
+/// WD279x sector length code for a sector size: 0-3 for 128, 256, 512 and 1024 bytes
+pub fn size_code(sector_size: usize) -> Option<u8> {
+    match sector_size {
+        128 => Some(0),
+        256 => Some(1),
+        512 => Some(2),
+        1024 => Some(3),
+        _ => None,
+    }
+}
+
+/// Raw sector disk image (.dsk or .img).
+///
+/// Sectors are stored in order of track, then side, then sector number, with
+/// no header, which is the layout MicroBee emulators use for both extensions.
+pub struct DiskImage {
+    pub geometry: Geometry,
+    data: Vec<u8>,
+    pub path: Option<PathBuf>, // File the image is saved back to
+    pub write_protected: bool,
+    dirty: bool,               // Written since it was loaded or saved
+}
+
+impl DiskImage {
+    /// Create an image with every sector formatted
+    pub fn blank(geometry: Geometry) -> Self {
+        DiskImage {
+            geometry,
+            data: vec![FORMAT_FILLER; geometry.size()],
+            path: None,
+            write_protected: false,
+            dirty: false,
+        }
+    }
+
+    /// Wrap image bytes, guessing the geometry from their length when none is given
+    pub fn from_bytes(data: Vec<u8>, geometry: Option<Geometry>) -> Result<Self, String> {
+        let geometry = match geometry {
+            Some(geometry) => geometry,
+            None => Geometry::from_size(data.len())
+                .ok_or_else(|| format!("cannot tell the geometry of a {}-byte image", data.len()))?,
+        };
+        if data.len() != geometry.size() {
+            return Err(format!("image is {} bytes but {} needs {}", data.len(), geometry, geometry.size()));
+        }
+        Ok(DiskImage { geometry, data, path: None, write_protected: false, dirty: false })
+    }
+
+    /// Load an image file, or start a blank one that is saved there once written if it does not exist
+    pub fn open(path: &Path, geometry: Option<Geometry>) -> Result<Self, String> {
+        let mut image = if path.exists() {
+            let data = std::fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+            DiskImage::from_bytes(data, geometry).map_err(|err| format!("{}: {}", path.display(), err))?
+        } else {
+            DiskImage::blank(geometry.unwrap_or(Geometry::DS80))
+        };
+        image.path = Some(path.to_path_buf());
+        Ok(image)
+    }
+
+    /// Write the image back to its file if it has changed
+    pub fn save(&mut self) -> Result<(), String> {
+        if let (true, Some(path)) = (self.dirty, &self.path) {
+            std::fs::write(path, &self.data).map_err(|err| format!("{}: {}", path.display(), err))?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// The whole image
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Whether the image has been written since it was loaded or saved
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn offset(&self, track: u8, side: u8, sector: u8) -> Option<usize> {
+        let geometry = &self.geometry;
+        let index = sector.checked_sub(geometry.first_sector)?;
+        if track >= geometry.tracks || side >= geometry.sides || index >= geometry.sectors {
+            return None;
+        }
+        let track_index = track as usize * geometry.sides as usize + side as usize;
+        Some((track_index * geometry.sectors as usize + index as usize) * geometry.sector_size)
+    }
+
+    /// Contents of a sector, or None if it is not on the disk
+    pub fn sector(&self, track: u8, side: u8, sector: u8) -> Option<&[u8]> {
+        let start = self.offset(track, side, sector)?;
+        Some(&self.data[start..start + self.geometry.sector_size])
+    }
+
+    /// Overwrite a sector; `data` shorter than a sector leaves the rest unchanged
+    pub fn write_sector(&mut self, track: u8, side: u8, sector: u8, data: &[u8]) -> Result<(), String> {
+        if self.write_protected {
+            return Err("disk is write protected".to_string());
+        }
+        let start = self
+            .offset(track, side, sector)
+            .ok_or_else(|| format!("no sector {} on track {} side {}", sector, track, side))?;
+        let length = data.len().min(self.geometry.sector_size);
+        self.data[start..start + length].copy_from_slice(&data[..length]);
+        self.dirty = true;
+        Ok(())
+    }
+}
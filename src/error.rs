@@ -0,0 +1,55 @@
+use std::fmt;
+
+/// Direction of a failed memory access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+impl fmt::Display for AccessKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessKind::Read => write!(f, "read"),
+            AccessKind::Write => write!(f, "write"),
+        }
+    }
+}
+
+/// Everything that can stop the emulated machine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmuError {
+    OutOfBounds { addr: usize, kind: AccessKind }, // Access past the end of memory
+    UnknownOpcode { opcode: u8, pc: u16 },         // `pc` is the address of the opcode
+    DivideByZero { pc: u16 },                      // DIV with reg_b == 0
+    StackOverflow { sp: u16 },                     // Push below the stack limit
+    StackUnderflow { sp: u16 },                    // Pop above the stack top
+    WriteToRom { addr: usize },                    // Write rejected by a read-only region
+    BusFault { addr: usize, message: String },     // A device failed to complete an access
+    WaitWithInterruptsDisabled { pc: u16 },        // `pc` is the address of the WAIT
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmuError::OutOfBounds { addr, kind } => {
+                write!(f, "Memory {} out of bounds at address: {:04X}", kind, addr)
+            }
+            EmuError::UnknownOpcode { opcode, pc } => {
+                write!(f, "Unknown instruction: 0x{:02X} at PC: 0x{:04X}", opcode, pc)
+            }
+            EmuError::DivideByZero { pc } => write!(f, "Division by zero at PC: 0x{:04X}", pc),
+            EmuError::StackOverflow { sp } => write!(f, "Stack overflow at SP: 0x{:04X}", sp),
+            EmuError::StackUnderflow { sp } => write!(f, "Stack underflow at SP: 0x{:04X}", sp),
+            EmuError::WriteToRom { addr } => write!(f, "Write to ROM at address: {:04X}", addr),
+            EmuError::BusFault { addr, message } => {
+                write!(f, "Bus fault at address: {:04X}: {}", addr, message)
+            }
+            EmuError::WaitWithInterruptsDisabled { pc } => {
+                write!(f, "WAIT with interrupts disabled at PC: 0x{:04X}", pc)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EmuError {}
@@ -0,0 +1,89 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// Number of IRQ lines on the controller
+pub const IRQ_LINES: u8 = 8;
+
+/// Interrupt controller shared between the CPU and the devices that raise IRQs.
+///
+/// Each line latches a request when a device asserts it. The CPU samples the
+/// controller between instructions and, when interrupts are enabled, services
+/// the lowest numbered unmasked line, clearing its request.
+#[derive(Default)]
+pub struct InterruptController {
+    pending: Rc<Cell<u8>>, // One bit per line with an outstanding request
+    pub mask: u8,          // Set bits block the matching line
+}
+
+/// Handle a device keeps to assert one IRQ line
+#[derive(Clone)]
+pub struct IrqLine {
+    line: u8,
+    pending: Rc<Cell<u8>>,
+}
+
+impl InterruptController {
+    /// Create a controller with no pending requests and every line unmasked
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hand out a handle for `line` that a device can assert
+    pub fn line(&self, line: u8) -> IrqLine {
+        assert!(line < IRQ_LINES, "IRQ line {} out of range", line);
+        IrqLine {
+            line,
+            pending: Rc::clone(&self.pending),
+        }
+    }
+
+    /// Latch a request on `line`
+    pub fn raise(&self, line: u8) {
+        self.line(line).assert();
+    }
+
+    /// Withdraw a request on `line` that has not been serviced yet
+    pub fn clear(&self, line: u8) {
+        self.line(line).release();
+    }
+
+    /// Bitmap of all latched requests, masked or not
+    pub fn requests(&self) -> u8 {
+        self.pending.get()
+    }
+
+    /// The highest priority (lowest numbered) unmasked line with a request
+    pub fn pending(&self) -> Option<u8> {
+        let active = self.pending.get() & !self.mask;
+        (active != 0).then(|| active.trailing_zeros() as u8)
+    }
+
+    /// Take the highest priority request, clearing it so it is serviced once
+    pub fn acknowledge(&self) -> Option<u8> {
+        let line = self.pending()?;
+        self.clear(line);
+        Some(line)
+    }
+}
+
+impl IrqLine {
+    /// The line number this handle drives
+    pub fn number(&self) -> u8 {
+        self.line
+    }
+
+    /// Request an interrupt
+    pub fn assert(&self) {
+        self.pending.set(self.pending.get() | (1 << self.line));
+    }
+
+    /// Withdraw the request if the CPU has not serviced it yet
+    pub fn release(&self) {
+        self.pending.set(self.pending.get() & !(1 << self.line));
+    }
+
+    /// Whether a request on this line is still waiting to be serviced
+    pub fn is_asserted(&self) -> bool {
+        self.pending.get() & (1 << self.line) != 0
+    }
+}
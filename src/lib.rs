@@ -0,0 +1,20 @@
+pub mod assembler; // Two-pass assembler for the MBOS instruction set
+pub mod banked_memory; // Bank-switched memory larger than 64K
+pub mod bus;       // Bus trait the CPU reads and writes through
+pub mod cpu;       // CPU core and instruction set
+pub mod devices;   // Device trait and peripherals
+pub mod disk;      // Raw sector floppy disk images
+pub mod disassembler; // Opcode-table driven disassembler
+pub mod error;     // Emulator error type
+pub mod interrupt; // Interrupt controller and IRQ lines
+pub mod memory;    // Flat byte-addressed memory
+pub mod memory_map; // Region-based RAM/ROM/mirror/unmapped address space
+pub mod mmio;      // Memory-mapped I/O routing devices onto the bus
+pub mod monitor;   // Interactive monitor/debugger
+pub mod opcodes;   // Shared opcode table
+pub mod png;       // Minimal PNG encoder for frame dumps
+pub mod ports;     // Port-based I/O space for IN and OUT
+pub mod serial;    // Host ends of the serial port: stdio or a Unix socket
+pub mod tape;      // Cassette tapes as compact images or Kansas City Standard audio
+pub mod terminal;  // Raw-mode host terminal and background stdin reader
+pub mod wav;       // PCM WAV reading and writing for sound and tapes
@@ -1,28 +1,923 @@
-mod memory; // Import memory.rs as a module
-mod cpu;    // Import cpu.rs as a module
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::rc::Rc;
+use std::sync::mpsc::Receiver;
 
-use cpu::CPU; // Bring CPU into scope
+use mbos::assembler;
+use mbos::banked_memory::{BankedMemory, WindowSize};
+use mbos::bus::Bus;
+use mbos::cpu::{MICROBEE_CLOCK_HZ, StepOutcome, VECTOR_TABLE_SIZE, CPU}; // Bring CPU into scope
+use mbos::devices::block::{BLOCK_PORT, BlockDevice};
+use mbos::devices::cassette::{CASSETTE_PORT, Cassette};
+use mbos::devices::fdc::{DRIVES, FDC_PORT, Fdc};
+use mbos::devices::keyboard::{KEYBOARD_PORT, Keyboard};
+use mbos::disk::{DiskImage, Geometry};
+use mbos::devices::rtc::{DateTime, RTC_PORT, Rtc, TIME_RANGE, TimeSource};
+use mbos::devices::speaker::{SPEAKER_PORT, Speaker};
+use mbos::devices::timer::{TIMER_PORT, Timer};
+use mbos::devices::uart::{UART_PORT, Uart};
+use mbos::devices::video::{ImageFormat, MICROBEE_VIDEO_BASE, VIDEO_RANGE_SIZE, Video};
+use mbos::disassembler;
+use mbos::interrupt::IRQ_LINES;
+use mbos::memory::Memory;
+use mbos::memory_map::{MemoryMap, PRESETS, RegionKind};
+use mbos::mmio::MmioBus;
+use mbos::monitor::Monitor;
+use mbos::serial::SerialBridge;
+use mbos::tape::{Baud, Tape};
+use mbos::terminal::{self, RawMode};
 
-fn main() -> Result<(), String> {
-    let mut cpu = CPU::new(64 * 1024); // CPU with 64KB of memory
+const USAGE: &str = "Usage: mbos <command> [options]
 
-    // Program: Load 10, increment it, and store the result
-    cpu.memory.write(0, 0x13)?; // LDA (Load direct value into accumulator)
-    cpu.memory.write(1, 10)?;   // Value to load: 10
-    cpu.memory.write(2, 0x07)?; // INC
-    cpu.memory.write(3, 0x02)?; // STORE
-    cpu.memory.write(4, 0x20)?; // Address low byte
-    cpu.memory.write(5, 0x00)?; // Address high byte
-    cpu.memory.write(6, 0xFF)?; // HALT
+Commands:
+  run <image> [--load-addr A] [--entry A] [--mem-size N] [--max-cycles N]
+      [--clock-hz N] [--memory-map <file>] [--banked-memory N] [--bank-window 16K|32K]
+      [--open-port V] [--vector-base A] [--stack A] [--stack-limit A]
+      [--trace] [--trace-ports]
+      [--video] [--char-rom <file>] [--display] [--screen-dump <file>]
+      [--dump-frames <dir>] [--dump-every N] [--dump-format ppm|png]
+      [--keyboard] [--keys <script>] [--keyboard-irq N]
+      [--serial stdio|unix:<path>] [--serial-irq N] [--serial-baud N]
+      [--timer] [--timer-irq N]
+      [--rtc] [--rtc-start <time>] [--rtc-nvram <file>] [--rtc-irq N]
+      [--disk <file>]... [--disk-geometry <geometry>] [--fdc-irq N]
+      [--block <file>] [--block-size N] [--block-irq N]
+      [--speaker <file.wav>] [--sample-rate N]
+      [--tape <file>] [--tape-out <file>] [--tape-baud 300|1200] [--tape-irq N]
+      Load a binary image and run it until HALT
+  debug <image> [--symbols <file>] [options of run except --max-cycles, --trace,
+      --trace-ports, --display, --screen-dump, --dump-frames, --keyboard and --serial]
+      Load a binary image into the interactive monitor
+  asm <source> [-o <output>] [--symbols <file>]
+      Assemble a source file into a binary image
+  disasm <image> [--org A]
+      List a binary image as assembler source
 
-    // Run the CPU
-    cpu.run();
+Addresses and sizes accept decimal, $hex, 0xhex or %binary; sizes may end in K.
+A memory map file declares RAM, ROM, mirrored and unmapped regions and replaces --mem-size.
+--memory-map microbee32k selects the built-in 32K MicroBee layout, with its ROM sockets
+empty for images loaded with --load-addr.
+--banked-memory gives the machine N bytes of physical RAM switched into the 64K address
+space in 16K or 32K windows (32K by default) through the bank latch port.
+--open-port sets the value IN reads from ports no device answers (default $FF).
+IRQ line N jumps to the 16-bit handler address at vector base + 2N. The 16-byte vector
+table sits at the top of the highest RAM, below the video RAM with --video, and the stack
+grows down from just under it to the bottom of that RAM region. --vector-base moves the
+table; --stack sets the initial SP and --stack-limit the lowest address the stack may use.
+--video maps the 64x16 screen RAM at $F000 and PCG RAM at $F800. --display redraws it in
+the terminal every frame; --screen-dump writes it on exit as text, or as an image when
+the file name ends in .ppm or .png. --dump-frames writes every Nth frame (default every
+frame) to <dir>/frame-NNNNNN.ppm, or .png with --dump-format png. All of these imply
+--video, as does --char-rom.
+--keyboard feeds keys typed at the terminal (or piped to stdin) to the keyboard ports
+$18 (data) and $19 (status); press Ctrl-] to stop the emulator. --keys presses the keys
+in a script of `TIME TEXT` lines, TIME in cycles or with an ms or s suffix.
+--serial attaches a UART at ports $20 (data), $21 (status) and $22 (control) and, while
+running, bridges it to stdin/stdout or to a client of a Unix domain socket. For a pty,
+connect with: socat PTY,link=/tmp/mbee,raw UNIX-CONNECT:<path>
+--timer attaches an interval timer at ports $30-$35 (reload low/high, count low/high,
+control, status); --timer-irq also connects its expiry interrupt.
+--rtc attaches an MC146818-style clock with its address port at $04 and data port at $07.
+It follows the host clock unless --rtc-start gives a fixed start in 1970-2069,
+YYYY-MM-DDTHH:MM:SS (UTC) or Unix seconds, that advances with emulated time. --rtc-nvram
+loads its 50 bytes of battery-backed RAM from a file and saves them back when the program
+changed them.
+--disk attaches a WD2793-style floppy controller at ports $44-$47 (command/status, track,
+sector, data) with its drive select latch at $48, and puts a raw .dsk or .img sector
+image in the next drive, up to four. Images are saved back on exit if written; a missing
+file starts as a blank, formatted disk and is only created once written. Geometry is
+taken from the file size (400K DS40, 800K DS80) unless --disk-geometry gives ds40, ss80,
+ds80 or TRACKSxSIDESxSECTORSxSIZE.
+--block attaches a paravirtual block device at ports $60-$67 (block number low/mid/high,
+address low/high, count, command/status, control) that copies 512-byte blocks between
+the file and memory. A missing file is created with --block-size bytes (default 1024K).
+--speaker attaches the 1-bit speaker on bit 6 of port $02 and, on exit, writes what it
+played as a mono 16-bit WAV file at --sample-rate samples per second (default 44100).
+--tape and --tape-out attach a cassette interface at ports $70 (data), $71 (status) and
+$72 (control) that plays the --tape file and, on exit, saves what was recorded to the
+--tape-out file, at 300 or 1200 baud (default 300). Files ending in .wav are Kansas City
+Standard audio; anything else is a compact TAP_DGOS_MBEE tape image.";
 
-    // Verify the result in memory
-    match cpu.memory.read(0x0020) {
-        Ok(value) => println!("Result: {}", value),
-        Err(err) => println!("Error: {}", err),
+/// Process exit codes
+const EXIT_HALTED: u8 = 0; // Program executed HALT
+const EXIT_ERROR: u8 = 1; // Emulation stopped on an error
+const EXIT_USAGE: u8 = 2; // Bad arguments or unreadable files
+const EXIT_CYCLE_LIMIT: u8 = 3; // --max-cycles reached before HALT
+const EXIT_STOPPED: u8 = 4; // Stopped from the host terminal
+
+/// Host key that stops the emulator while the terminal feeds the keyboard (Ctrl-])
+const STOP_KEY: u8 = 0x1D;
+
+/// Cycles between polls of the host end of the serial port
+const SERIAL_POLL_CYCLES: u64 = 1000;
+
+/// Highest --sample-rate accepted for speaker recordings
+const MAX_SAMPLE_RATE: u32 = 384_000;
+
+/// Where `--serial` connects the UART
+enum SerialTarget {
+    Stdio,
+    Socket(PathBuf),
+}
+
+/// Options shared by `run` and `debug`
+struct MachineOptions {
+    image: PathBuf,
+    load_addr: u16,
+    entry: Option<u16>,
+    mem_size: usize,
+    max_cycles: Option<u64>,
+    clock_hz: u64,
+    memory_map: Option<PathBuf>,
+    banked_memory: Option<usize>,
+    bank_window: WindowSize,
+    open_port: u8,
+    vector_base: Option<u16>,
+    stack: Option<u16>,
+    stack_limit: Option<u16>,
+    symbols: Option<PathBuf>,
+    trace: bool,
+    trace_ports: bool,
+    video: bool,
+    char_rom: Option<PathBuf>,
+    display: bool,
+    screen_dump: Option<PathBuf>,
+    dump_frames: Option<PathBuf>,
+    dump_every: u64,
+    dump_format: ImageFormat,
+    keyboard: bool,
+    keys: Option<PathBuf>,
+    keyboard_irq: Option<u8>,
+    serial: Option<SerialTarget>,
+    serial_irq: Option<u8>,
+    serial_baud: u64,
+    timer: bool,
+    timer_irq: Option<u8>,
+    rtc: bool,
+    rtc_start: Option<i64>,
+    rtc_nvram: Option<PathBuf>,
+    rtc_irq: Option<u8>,
+    disks: Vec<PathBuf>,
+    disk_geometry: Option<Geometry>,
+    fdc_irq: Option<u8>,
+    block: Option<PathBuf>,
+    block_size: usize,
+    block_irq: Option<u8>,
+    speaker: Option<PathBuf>,
+    sample_rate: u32,
+    tape: Option<PathBuf>,
+    tape_out: Option<PathBuf>,
+    tape_baud: Baud,
+    tape_irq: Option<u8>,
+}
+
+/// Host-side handles to the devices attached to the machine
+#[derive(Default)]
+struct Peripherals {
+    video: Option<Rc<RefCell<Video>>>,
+    keyboard: Option<Rc<RefCell<Keyboard>>>,
+    uart: Option<Rc<RefCell<Uart>>>,
+    rtc: Option<Rc<RefCell<Rtc>>>,
+    fdc: Option<Rc<RefCell<Fdc>>>,
+    speaker: Option<Rc<RefCell<Speaker>>>,
+    cassette: Option<Rc<RefCell<Cassette>>>,
+}
+
+/// Host-side input and output exchanged with the devices while running
+struct HostIo {
+    _raw_mode: Option<RawMode>,  // Restores the terminal when dropped
+    keys: Option<Receiver<u8>>,  // Terminal input for the keyboard
+    serial: Option<SerialBridge>,
+    serial_input: VecDeque<u8>,  // Host bytes waiting for room in the UART's receive FIFO
+    next_poll: u64,              // Cycle count at which to poll the serial bridge again
+}
+
+impl MachineOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = MachineOptions {
+            image: PathBuf::new(),
+            load_addr: 0,
+            entry: None,
+            mem_size: 64 * 1024,
+            max_cycles: None,
+            clock_hz: MICROBEE_CLOCK_HZ,
+            memory_map: None,
+            banked_memory: None,
+            bank_window: WindowSize::K32,
+            open_port: 0xFF,
+            vector_base: None,
+            stack: None,
+            stack_limit: None,
+            symbols: None,
+            trace: false,
+            trace_ports: false,
+            video: false,
+            char_rom: None,
+            display: false,
+            screen_dump: None,
+            dump_frames: None,
+            dump_every: 1,
+            dump_format: ImageFormat::Ppm,
+            keyboard: false,
+            keys: None,
+            keyboard_irq: None,
+            serial: None,
+            serial_irq: None,
+            serial_baud: 0,
+            timer: false,
+            timer_irq: None,
+            rtc: false,
+            rtc_start: None,
+            rtc_nvram: None,
+            rtc_irq: None,
+            disks: Vec::new(),
+            disk_geometry: None,
+            fdc_irq: None,
+            block: None,
+            block_size: 1024 * 1024,
+            block_irq: None,
+            speaker: None,
+            sample_rate: 44100,
+            tape: None,
+            tape_out: None,
+            tape_baud: Baud::B300,
+            tape_irq: None,
+        };
+        let mut image = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--load-addr" => options.load_addr = parse_address(value()?)?,
+                "--entry" => options.entry = Some(parse_address(value()?)?),
+                "--mem-size" => options.mem_size = parse_size(value()?)?,
+                "--max-cycles" => options.max_cycles = Some(parse_size(value()?)? as u64),
+                "--clock-hz" => options.clock_hz = parse_size(value()?)? as u64,
+                "--memory-map" => options.memory_map = Some(PathBuf::from(value()?)),
+                "--banked-memory" => options.banked_memory = Some(parse_size(value()?)?),
+                "--bank-window" => {
+                    options.bank_window = match value()?.to_ascii_uppercase().as_str() {
+                        "16K" => WindowSize::K16,
+                        "32K" => WindowSize::K32,
+                        other => return Err(format!("Bank window must be 16K or 32K, got {}", other)),
+                    }
+                }
+                "--symbols" => options.symbols = Some(PathBuf::from(value()?)),
+                "--open-port" => {
+                    let text = value()?;
+                    options.open_port = u8::try_from(parse_address(text)?)
+                        .map_err(|_| format!("Port value must fit in a byte: {}", text))?;
+                }
+                "--vector-base" => options.vector_base = Some(parse_address(value()?)?),
+                "--stack" => options.stack = Some(parse_address(value()?)?),
+                "--stack-limit" => options.stack_limit = Some(parse_address(value()?)?),
+                "--trace" => options.trace = true,
+                "--trace-ports" => options.trace_ports = true,
+                "--video" => options.video = true,
+                "--char-rom" => options.char_rom = Some(PathBuf::from(value()?)),
+                "--display" => options.display = true,
+                "--screen-dump" => options.screen_dump = Some(PathBuf::from(value()?)),
+                "--dump-frames" => options.dump_frames = Some(PathBuf::from(value()?)),
+                "--dump-every" => options.dump_every = parse_size(value()?)? as u64,
+                "--dump-format" => {
+                    let text = value()?;
+                    options.dump_format = ImageFormat::from_extension(text)
+                        .ok_or_else(|| format!("Frame format must be ppm or png, got {}", text))?;
+                }
+                "--keyboard" => options.keyboard = true,
+                "--keys" => options.keys = Some(PathBuf::from(value()?)),
+                "--keyboard-irq" => options.keyboard_irq = Some(parse_irq(value()?)?),
+                "--serial" => {
+                    let text = value()?;
+                    options.serial = Some(match text.strip_prefix("unix:") {
+                        Some(path) => SerialTarget::Socket(PathBuf::from(path)),
+                        None if text == "stdio" => SerialTarget::Stdio,
+                        None => return Err(format!("Serial target must be stdio or unix:<path>, got {}", text)),
+                    });
+                }
+                "--serial-irq" => options.serial_irq = Some(parse_irq(value()?)?),
+                "--serial-baud" => options.serial_baud = parse_size(value()?)? as u64,
+                "--timer" => options.timer = true,
+                "--timer-irq" => options.timer_irq = Some(parse_irq(value()?)?),
+                "--rtc" => options.rtc = true,
+                "--rtc-start" => {
+                    let text = value()?;
+                    let start = match DateTime::parse(text) {
+                        Some(time) => Some(time.to_unix()),
+                        None => text.parse().ok().filter(|seconds| TIME_RANGE.contains(seconds)),
+                    };
+                    let start = start.ok_or_else(|| format!("Invalid RTC start time (1970-2069): {}", text))?;
+                    options.rtc_start = Some(start);
+                }
+                "--rtc-nvram" => options.rtc_nvram = Some(PathBuf::from(value()?)),
+                "--rtc-irq" => options.rtc_irq = Some(parse_irq(value()?)?),
+                "--disk" => options.disks.push(PathBuf::from(value()?)),
+                "--disk-geometry" => {
+                    let text = value()?;
+                    options.disk_geometry =
+                        Some(Geometry::parse(text).ok_or_else(|| format!("Invalid disk geometry: {}", text))?);
+                }
+                "--fdc-irq" => options.fdc_irq = Some(parse_irq(value()?)?),
+                "--block" => options.block = Some(PathBuf::from(value()?)),
+                "--block-size" => options.block_size = parse_size(value()?)?,
+                "--block-irq" => options.block_irq = Some(parse_irq(value()?)?),
+                "--speaker" => options.speaker = Some(PathBuf::from(value()?)),
+                "--sample-rate" => {
+                    let text = value()?;
+                    options.sample_rate = u32::try_from(parse_size(text)?)
+                        .ok()
+                        .filter(|rate| (1..=MAX_SAMPLE_RATE).contains(rate))
+                        .ok_or_else(|| format!("Sample rate must be between 1 and {}, got {}", MAX_SAMPLE_RATE, text))?;
+                }
+                "--tape" => options.tape = Some(PathBuf::from(value()?)),
+                "--tape-out" => options.tape_out = Some(PathBuf::from(value()?)),
+                "--tape-baud" => {
+                    let text = value()?;
+                    options.tape_baud =
+                        Baud::parse(text).ok_or_else(|| format!("Tape baud rate must be 300 or 1200, got {}", text))?;
+                }
+                "--tape-irq" => options.tape_irq = Some(parse_irq(value()?)?),
+                _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+                _ if image.is_none() => image = Some(PathBuf::from(arg)),
+                _ => return Err(format!("Unexpected argument: {}", arg)),
+            }
+        }
+        options.image = image.ok_or("Missing image file")?;
+        if options.clock_hz == 0 {
+            return Err("Clock frequency must be above zero".to_string());
+        }
+        if options.mem_size == 0 || options.mem_size > 0x10000 {
+            return Err(format!("Memory size must be between 1 and 64K, got {}", options.mem_size));
+        }
+        options.video |= options.display
+            || options.screen_dump.is_some()
+            || options.dump_frames.is_some()
+            || options.char_rom.is_some();
+        if options.dump_every == 0 {
+            return Err("--dump-every must be at least 1".to_string());
+        }
+        if options.keyboard && matches!(options.serial, Some(SerialTarget::Stdio)) {
+            return Err("--keyboard and --serial stdio cannot both read stdin".to_string());
+        }
+        if options.disks.len() > DRIVES {
+            return Err(format!("At most {} disks can be attached", DRIVES));
+        }
+        if options.memory_map.is_some() && options.banked_memory.is_some() {
+            return Err("--memory-map and --banked-memory cannot be combined".to_string());
+        }
+        Ok(options)
+    }
+
+    /// The first option given that only `run` acts on: the monitor owns the terminal
+    /// and steps the CPU itself, so host I/O, tracing and the cycle limit are not set up
+    fn run_only_option(&self) -> Option<&'static str> {
+        [
+            (self.max_cycles.is_some(), "--max-cycles"),
+            (self.trace, "--trace"),
+            (self.trace_ports, "--trace-ports"),
+            (self.display, "--display"),
+            (self.screen_dump.is_some(), "--screen-dump"),
+            (self.dump_frames.is_some(), "--dump-frames"),
+            (self.keyboard, "--keyboard"),
+            (self.serial.is_some(), "--serial"),
+        ]
+        .into_iter()
+        .find_map(|(given, option)| given.then_some(option))
+    }
+
+    /// Read the `--memory-map` description, if one was given
+    fn memory_map(&self) -> Result<Option<MemoryMap>, String> {
+        let Some(path) = &self.memory_map else {
+            return Ok(None);
+        };
+        if !path.exists()
+            && let Some((name, text)) = PRESETS.iter().find(|(name, _)| path.as_os_str() == *name)
+        {
+            return MemoryMap::parse(text, Path::new("."))
+                .map(Some)
+                .map_err(|err| format!("{}: {}", name, err));
+        }
+        let text = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+        MemoryMap::parse(&text, base_dir)
+            .map(Some)
+            .map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    /// Create the `--banked-memory` store, if one was requested
+    fn banked_memory(&self) -> Result<Option<BankedMemory>, String> {
+        self.banked_memory
+            .map(|size| BankedMemory::new(size, self.bank_window))
+            .transpose()
+    }
+
+    /// Map the requested devices onto the CPU's buses, returning the host's handles to them
+    fn attach_devices<B: Bus>(&self, cpu: &mut CPU<MmioBus<B>>) -> Result<Peripherals, String> {
+        let mut peripherals = Peripherals::default();
+        if self.video {
+            let mut video = Video::new(self.clock_hz);
+            if let Some(path) = &self.char_rom {
+                video
+                    .load_char_rom(&read_file(path)?)
+                    .map_err(|err| format!("{}: {}", path.display(), err))?;
+            }
+            let video = Rc::new(RefCell::new(video));
+            let range = MICROBEE_VIDEO_BASE..MICROBEE_VIDEO_BASE + VIDEO_RANGE_SIZE;
+            cpu.memory.map_io(range, Box::new(Rc::clone(&video)))?;
+            peripherals.video = Some(video);
+        }
+        if self.keyboard || self.keys.is_some() || self.keyboard_irq.is_some() {
+            let mut keyboard = Keyboard::new();
+            if let Some(path) = &self.keys {
+                let script = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+                keyboard
+                    .load_script(&script, self.clock_hz)
+                    .map_err(|err| format!("{}: {}", path.display(), err))?;
+            }
+            keyboard.irq = self.keyboard_irq.map(|line| cpu.interrupts.line(line));
+            let keyboard = Rc::new(RefCell::new(keyboard));
+            cpu.ports
+                .map_ports(KEYBOARD_PORT..=KEYBOARD_PORT + 1, Box::new(Rc::clone(&keyboard)))?;
+            peripherals.keyboard = Some(keyboard);
+        }
+        if self.serial.is_some() || self.serial_irq.is_some() {
+            let mut uart = Uart::new();
+            uart.set_baud(self.serial_baud, self.clock_hz);
+            uart.irq = self.serial_irq.map(|line| cpu.interrupts.line(line));
+            let uart = Rc::new(RefCell::new(uart));
+            cpu.ports.map_ports(UART_PORT..=UART_PORT + 2, Box::new(Rc::clone(&uart)))?;
+            peripherals.uart = Some(uart);
+        }
+        if self.timer || self.timer_irq.is_some() {
+            let mut timer = Timer::new();
+            timer.irq = self.timer_irq.map(|line| cpu.interrupts.line(line));
+            cpu.ports.map_ports(TIMER_PORT..=TIMER_PORT + 5, Box::new(timer))?;
+        }
+        if self.rtc || self.rtc_start.is_some() || self.rtc_nvram.is_some() || self.rtc_irq.is_some() {
+            let source = match self.rtc_start {
+                Some(start) => TimeSource::Fixed { start },
+                None => TimeSource::Host,
+            };
+            let mut rtc = Rtc::new(source, self.clock_hz);
+            if let Some(path) = &self.rtc_nvram
+                && path.exists()
+            {
+                rtc.load_nvram(&read_file(path)?);
+            }
+            rtc.irq = self.rtc_irq.map(|line| cpu.interrupts.line(line));
+            let rtc = Rc::new(RefCell::new(rtc));
+            cpu.ports.map_ports(RTC_PORT..=RTC_PORT + 3, Box::new(Rc::clone(&rtc)))?;
+            peripherals.rtc = Some(rtc);
+        }
+        if !self.disks.is_empty() || self.fdc_irq.is_some() {
+            let mut fdc = Fdc::new();
+            for (drive, path) in self.disks.iter().enumerate() {
+                fdc.insert(drive, DiskImage::open(path, self.disk_geometry)?);
+            }
+            fdc.irq = self.fdc_irq.map(|line| cpu.interrupts.line(line));
+            let fdc = Rc::new(RefCell::new(fdc));
+            cpu.ports.map_ports(FDC_PORT..=FDC_PORT + 4, Box::new(Rc::clone(&fdc)))?;
+            peripherals.fdc = Some(fdc);
+        }
+        if let Some(path) = &self.block {
+            let mut block = BlockDevice::open(path, self.block_size)?;
+            block.irq = self.block_irq.map(|line| cpu.interrupts.line(line));
+            cpu.ports.map_ports(BLOCK_PORT..=BLOCK_PORT + 7, Box::new(block))?;
+        }
+        if self.speaker.is_some() {
+            let speaker = Rc::new(RefCell::new(Speaker::new(self.clock_hz)));
+            cpu.ports.map_ports(SPEAKER_PORT..=SPEAKER_PORT, Box::new(Rc::clone(&speaker)))?;
+            peripherals.speaker = Some(speaker);
+        }
+        if self.tape.is_some() || self.tape_out.is_some() || self.tape_irq.is_some() {
+            let input = match &self.tape {
+                Some(path) => Tape::load(path, self.tape_baud)?,
+                None => Tape::default(),
+            };
+            let mut cassette = Cassette::new(input, self.tape_baud, self.clock_hz);
+            cassette.irq = self.tape_irq.map(|line| cpu.interrupts.line(line));
+            let cassette = Rc::new(RefCell::new(cassette));
+            cpu.ports
+                .map_ports(CASSETTE_PORT..=CASSETTE_PORT + 2, Box::new(Rc::clone(&cassette)))?;
+            peripherals.cassette = Some(cassette);
+        }
+        Ok(peripherals)
+    }
+
+    /// The RAM the vector table and stack go at the top of: the highest of `ram`, cut
+    /// short below the video RAM when video is attached, as (start, end) inclusive
+    fn stack_region(&self, ram: &[RangeInclusive<usize>]) -> Option<(usize, usize)> {
+        let below = if self.video { MICROBEE_VIDEO_BASE } else { 0x10000 };
+        ram.iter()
+            .map(|range| (*range.start(), (*range.end()).min(below - 1)))
+            .filter(|&(start, end)| end >= start + VECTOR_TABLE_SIZE)
+            .max_by_key(|&(_, end)| end)
+    }
+
+    /// Create a CPU on `bus` with the devices attached, the image loaded and PC at the entry
+    /// point; `ram` lists the address ranges of the bus that are RAM
+    fn build_cpu<B: Bus>(
+        &self,
+        bus: B,
+        ram: &[RangeInclusive<usize>],
+    ) -> Result<(CPU<MmioBus<B>>, Peripherals), String> {
+        let bytes = read_file(&self.image)?;
+        let mut cpu = CPU::with_bus(MmioBus::new(bus));
+        if let Some((start, end)) = self.stack_region(ram) {
+            let base = (end + 1 - VECTOR_TABLE_SIZE) as u16;
+            cpu.vector_base = base;
+            cpu.set_stack(base, start as u16);
+        }
+        if let Some(base) = self.vector_base {
+            cpu.vector_base = base;
+        }
+        if self.stack.is_some() || self.stack_limit.is_some() {
+            let top = self.stack.unwrap_or(cpu.stack_top);
+            let limit = self.stack_limit.or(self.stack.map(|_| 0)).unwrap_or(cpu.stack_limit);
+            if top != 0 && limit >= top {
+                return Err(format!("Stack limit {:04X} must be below the stack top {:04X}", limit, top));
+            }
+            cpu.set_stack(top, limit);
+        }
+        let peripherals = self.attach_devices(&mut cpu)?;
+        cpu.memory
+            .load(self.load_addr as usize, &bytes)
+            .map_err(|err| format!("{}: {}", self.image.display(), err))?;
+        cpu.pc = self.entry.unwrap_or(self.load_addr);
+        if let Some(base) = self.vector_base {
+            cpu.vector_base = base;
+        }
+        cpu.clock_hz = self.clock_hz;
+        cpu.ports.unclaimed = self.open_port;
+        cpu.ports.set_trace(self.trace_ports);
+        Ok((cpu, peripherals))
+    }
+}
+
+impl Peripherals {
+    /// Save what outlives a run: the RTC's battery-backed RAM, written disks, recorded sound and tape
+    fn save(&self, options: &MachineOptions) -> Result<(), String> {
+        if let (Some(cassette), Some(path)) = (&self.cassette, &options.tape_out) {
+            cassette.borrow().recorded().save(path, options.tape_baud)?;
+        }
+        if let (Some(speaker), Some(path)) = (&self.speaker, &options.speaker) {
+            let mut bytes = Vec::new();
+            speaker
+                .borrow()
+                .write_wav(&mut bytes, options.sample_rate)
+                .map_err(|err| format!("{}: {}", path.display(), err))?;
+            write_file(path, &bytes)?;
+        }
+        if let Some(fdc) = &self.fdc {
+            fdc.borrow_mut().save()?;
+        }
+        if let (Some(rtc), Some(path)) = (&self.rtc, &options.rtc_nvram) {
+            let mut rtc = rtc.borrow_mut();
+            if rtc.take_nvram_dirty() || !path.exists() {
+                write_file(path, rtc.nvram())?;
+            }
+        }
+        Ok(())
     }
+}
+
+/// Address ranges of the RAM regions in `map`
+fn ram_regions(map: &MemoryMap) -> Vec<RangeInclusive<usize>> {
+    map.regions()
+        .iter()
+        .filter(|region| region.kind == RegionKind::Ram)
+        .map(|region| region.start as usize..=region.end as usize)
+        .collect()
+}
+
+/// `run`: execute an image until HALT, an error or the cycle limit
+fn run_image(args: &[String]) -> Result<ExitCode, String> {
+    let options = MachineOptions::parse(args)?;
+    if let Some(map) = options.memory_map()? {
+        let ram = ram_regions(&map);
+        run_cpu(&options, options.build_cpu(map, &ram)?)
+    } else if let Some(banked) = options.banked_memory()? {
+        run_cpu(&options, options.build_cpu(banked, &[0..=0xFFFF])?)
+    } else {
+        let ram = [0..=options.mem_size - 1];
+        run_cpu(&options, options.build_cpu(Memory::new(options.mem_size), &ram)?)
+    }
+}
+
+fn run_cpu<B: Bus>(
+    options: &MachineOptions,
+    (mut cpu, peripherals): (CPU<B>, Peripherals),
+) -> Result<ExitCode, String> {
+    // Keep what the program wrote to disks, tapes and NVRAM even if the host side failed
+    let result = run_session(options, &mut cpu, &peripherals);
+    let saved = peripherals.save(options);
+    let code = result?;
+    saved?;
+
+    cpu.print_registers();
+    println!("Emulated time: {:.6}s at {} Hz", cpu.elapsed().as_secs_f64(), cpu.clock_hz);
+    Ok(ExitCode::from(code))
+}
+
+/// Run the CPU with the host connected until it stops, returning the exit code
+fn run_session<B: Bus>(options: &MachineOptions, cpu: &mut CPU<B>, peripherals: &Peripherals) -> Result<u8, String> {
+    let trace = options.trace;
+    let mut shown_frame = None;
+    let mut dumped_frame = None;
+    if let Some(dir) = &options.dump_frames {
+        std::fs::create_dir_all(dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+    }
+    let mut host = HostIo::open(options)?;
+
+    let code = loop {
+        if options.max_cycles.is_some_and(|max| cpu.cycles >= max) {
+            eprintln!("Cycle limit of {} reached", cpu.cycles);
+            break EXIT_CYCLE_LIMIT;
+        }
+        if !host.exchange(cpu.cycles, peripherals)? {
+            eprintln!("Stopped from the terminal");
+            break EXIT_STOPPED;
+        }
+        if trace && !cpu.waiting && let Ok(instruction) = disassembler::decode(&cpu.memory, cpu.pc) {
+            println!("{:<32} ; {}", instruction.to_string(), cpu.cycles);
+        }
+        match cpu.step() {
+            StepOutcome::Executed { .. } | StepOutcome::WaitingForInterrupt | StepOutcome::Breakpoint => {}
+            StepOutcome::Halted => break EXIT_HALTED,
+            StepOutcome::Trapped(err) => {
+                eprintln!("Execution error: {}", err);
+                break EXIT_ERROR;
+            }
+        }
+        for access in cpu.ports.take_trace() {
+            println!("{}", access);
+        }
+        if trace {
+            cpu.print_registers();
+        }
+        if options.display
+            && let Some(video) = &peripherals.video
+        {
+            let video = video.borrow();
+            if shown_frame != Some(video.frame_count()) {
+                shown_frame = Some(video.frame_count());
+                print!("{}", video.render_ansi());
+                let _ = std::io::stdout().flush();
+            }
+        }
+        if let (Some(dir), Some(video)) = (&options.dump_frames, &peripherals.video) {
+            let video = video.borrow();
+            let frame = video.frame_count();
+            if dumped_frame != Some(frame) && frame.is_multiple_of(options.dump_every) {
+                dumped_frame = Some(frame);
+                let path = dir.join(format!("frame-{:06}.{}", frame, options.dump_format.extension()));
+                write_frame(&path, &video, options.dump_format)?;
+            }
+        }
+    };
 
-    Ok(())
-}
\ No newline at end of file
+    if let Some(uart) = &peripherals.uart {
+        uart.borrow_mut().flush();
+    }
+    host.exchange(cpu.cycles, peripherals)?; // Deliver output still in flight
+    if options.display
+        && let Some(video) = &peripherals.video
+    {
+        print!("{}", video.borrow().render_ansi()); // Show the final state even mid-frame
+    }
+    if let (Some(path), Some(video)) = (&options.screen_dump, &peripherals.video) {
+        let video = video.borrow();
+        match ImageFormat::from_path(path) {
+            Some(format) => write_frame(path, &video, format)?,
+            None => write_file(path, format!("{}\n", video.text()).as_bytes())?,
+        }
+    }
+
+    Ok(code)
+}
+
+impl HostIo {
+    /// Put the terminal in raw mode and connect the serial bridge as the options ask
+    fn open(options: &MachineOptions) -> Result<Self, String> {
+        let serial = match &options.serial {
+            None => None,
+            Some(SerialTarget::Stdio) => Some(SerialBridge::stdio()),
+            Some(SerialTarget::Socket(path)) => {
+                eprintln!("Waiting for a serial connection on {}", path.display());
+                Some(SerialBridge::listen(path).map_err(|err| format!("{}: {}", path.display(), err))?)
+            }
+        };
+        let uses_terminal = options.keyboard || matches!(options.serial, Some(SerialTarget::Stdio));
+        Ok(HostIo {
+            _raw_mode: uses_terminal.then(RawMode::enable),
+            keys: options.keyboard.then(terminal::spawn_stdin_reader),
+            serial,
+            serial_input: VecDeque::new(),
+            next_poll: 0,
+        })
+    }
+
+    /// Move host input to the devices and device output to the host.
+    ///
+    /// Returns false once STOP_KEY arrives from the terminal.
+    fn exchange(&mut self, cycles: u64, peripherals: &Peripherals) -> Result<bool, String> {
+        if let (Some(input), Some(keyboard)) = (&self.keys, &peripherals.keyboard) {
+            for key in input.try_iter() {
+                if key == STOP_KEY {
+                    return Ok(false);
+                }
+                keyboard.borrow_mut().press(key);
+            }
+        }
+        if let (Some(serial), Some(uart)) = (&mut self.serial, &peripherals.uart) {
+            let mut uart = uart.borrow_mut();
+            let output = uart.take_output();
+            if !output.is_empty() {
+                serial.send(&output).map_err(|err| format!("Serial port: {}", err))?;
+            }
+            if cycles >= self.next_poll {
+                self.next_poll = cycles + SERIAL_POLL_CYCLES;
+                let input = serial.poll().map_err(|err| format!("Serial port: {}", err))?;
+                if matches!(serial, SerialBridge::Stdio(_)) && input.contains(&STOP_KEY) {
+                    return Ok(false);
+                }
+                self.serial_input.extend(input);
+            }
+            // Hold host input back rather than overrun the FIFO
+            while uart.rx_space() > 0
+                && let Some(byte) = self.serial_input.pop_front()
+            {
+                uart.receive(byte);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// `debug`: load an image and hand it to the monitor on stdin/stdout
+fn debug_image(args: &[String]) -> Result<ExitCode, String> {
+    let options = MachineOptions::parse(args)?;
+    if let Some(option) = options.run_only_option() {
+        return Err(format!("{} only applies to run", option));
+    }
+    if let Some(map) = options.memory_map()? {
+        let ram = ram_regions(&map);
+        debug_cpu(&options, options.build_cpu(map, &ram)?)
+    } else if let Some(banked) = options.banked_memory()? {
+        debug_cpu(&options, options.build_cpu(banked, &[0..=0xFFFF])?)
+    } else {
+        let ram = [0..=options.mem_size - 1];
+        debug_cpu(&options, options.build_cpu(Memory::new(options.mem_size), &ram)?)
+    }
+}
+
+fn debug_cpu<B: Bus>(options: &MachineOptions, (cpu, peripherals): (CPU<B>, Peripherals)) -> Result<ExitCode, String> {
+    let mut monitor = Monitor::new(cpu);
+    if let Some(path) = &options.symbols {
+        let text = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        monitor.symbols = assembler::parse_symbols(&text)
+            .map_err(|err| format!("{}:{}: {}", path.display(), err.line, err.message))?;
+    }
+    let result = monitor
+        .repl(std::io::stdin().lock(), std::io::stdout())
+        .map_err(|err| err.to_string());
+    let saved = peripherals.save(options);
+    result?;
+    saved?;
+    Ok(ExitCode::from(EXIT_HALTED))
+}
+
+/// `asm <source> [-o <output>] [--symbols <file>]`: assemble a source file to a binary image
+fn assemble_file(args: &[String]) -> Result<ExitCode, String> {
+    let mut source = None;
+    let mut output = None;
+    let mut symbols = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(PathBuf::from(args.next().ok_or("-o needs a file name")?)),
+            "--symbols" => symbols = Some(PathBuf::from(args.next().ok_or("--symbols needs a file name")?)),
+            _ if source.is_none() => source = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    let source = source.ok_or("Missing source file")?;
+    let output = output.unwrap_or_else(|| source.with_extension("bin"));
+
+    let text = std::fs::read_to_string(&source)
+        .map_err(|err| format!("{}: {}", source.display(), err))?;
+    let image = match assembler::assemble(&text) {
+        Ok(image) => image,
+        Err(err) => {
+            eprintln!("{}:{}: {}", source.display(), err.line, err.message);
+            return Ok(ExitCode::from(EXIT_ERROR));
+        }
+    };
+    write_file(&output, &image.bytes)?;
+    if let Some(path) = symbols {
+        write_file(&path, image.symbol_listing().as_bytes())?;
+    }
+    println!(
+        "Assembled {} bytes at ${:04X} into {}",
+        image.bytes.len(),
+        image.origin,
+        output.display()
+    );
+    Ok(ExitCode::from(EXIT_HALTED))
+}
+
+/// `disasm <image> [--org <address>]`: list a binary image as assembler source
+fn disassemble_file(args: &[String]) -> Result<ExitCode, String> {
+    let mut image = None;
+    let mut origin = 0;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--org" => origin = parse_address(args.next().ok_or("--org needs an address")?)?,
+            _ if image.is_none() => image = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    let image = image.ok_or("Missing image file")?;
+    let bytes = read_file(&image)?;
+
+    let mut memory = Memory::new(0x10000);
+    memory
+        .load(origin as usize, &bytes)
+        .map_err(|err| format!("{}: {}", image.display(), err))?;
+    let end = origin as usize + bytes.len();
+    let listing = disassembler::disassemble(&memory, origin as usize..end).map_err(|err| err.to_string())?;
+    for instruction in listing {
+        println!("{}", instruction);
+    }
+    Ok(ExitCode::from(EXIT_HALTED))
+}
+
+/// Parse an address in any notation the assembler accepts for numbers
+fn parse_address(text: &str) -> Result<u16, String> {
+    assembler::parse_number(text)
+        .and_then(|value| u16::try_from(value).ok())
+        .ok_or_else(|| format!("Invalid address: {}", text))
+}
+
+/// Parse an IRQ line number
+fn parse_irq(text: &str) -> Result<u8, String> {
+    assembler::parse_number(text)
+        .and_then(|value| u8::try_from(value).ok())
+        .filter(|&line| line < IRQ_LINES)
+        .ok_or_else(|| format!("IRQ line must be 0 to {}: {}", IRQ_LINES - 1, text))
+}
+
+/// Parse a count or size, allowing a `K` suffix for multiples of 1024
+fn parse_size(text: &str) -> Result<usize, String> {
+    let (digits, scale) = match text.strip_suffix(['K', 'k']) {
+        Some(digits) => (digits, 1024),
+        None => (text, 1),
+    };
+    assembler::parse_number(digits)
+        .and_then(|value| usize::try_from(value).ok())
+        .and_then(|value| value.checked_mul(scale))
+        .ok_or_else(|| format!("Invalid size: {}", text))
+}
+
+/// Rasterise the screen and save it as an image
+fn write_frame(path: &Path, video: &Video, format: ImageFormat) -> Result<(), String> {
+    let mut bytes = Vec::new();
+    video.rasterise().write(format, &mut bytes).map_err(|err| err.to_string())?;
+    write_file(path, &bytes)
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+fn write_file(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    std::fs::write(path, bytes).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    let rest = args.get(2..).unwrap_or_default();
+    let result = match args.get(1).map(String::as_str) {
+        Some("run") => run_image(rest),
+        Some("debug") => debug_image(rest),
+        Some("asm") => assemble_file(rest),
+        Some("disasm") => disassemble_file(rest),
+        Some("help" | "-h" | "--help") => {
+            println!("{}", USAGE);
+            return ExitCode::from(EXIT_HALTED);
+        }
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::from(EXIT_USAGE)
+        }
+    }
+}
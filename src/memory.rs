@@ -1,4 +1,6 @@
-﻿pub struct Memory {
+﻿use crate::error::{AccessKind, EmuError};
+
+pub struct Memory {
     pub(crate) data: Vec<u8>, // Memory stored as a vector of bytes
 }
 
@@ -15,19 +17,30 @@ impl Memory {
         self.data.len()
     }
 
+    /// Copy a block of bytes into memory starting at a certain address
+    pub fn load(&mut self, address: usize, bytes: &[u8]) -> Result<(), EmuError> {
+        let end = address + bytes.len();
+        if end > self.data.len() {
+            Err(EmuError::OutOfBounds { addr: address, kind: AccessKind::Write })
+        } else {
+            self.data[address..end].copy_from_slice(bytes);
+            Ok(())
+        }
+    }
+
     /// Read a byte from a certain address
-    pub fn read(&self, address: usize) -> Result<u8, String> {
+    pub fn read(&self, address: usize) -> Result<u8, EmuError> {
         if address >= self.data.len() {
-            Err(format!("Memory read out of bounds at address: {:04X}", address))
+            Err(EmuError::OutOfBounds { addr: address, kind: AccessKind::Read })
         } else {
             Ok(self.data[address])
         }
     }
 
     /// Write a byte to a certain address
-    pub fn write(&mut self, address: usize, value: u8) -> Result<(), String> {
+    pub fn write(&mut self, address: usize, value: u8) -> Result<(), EmuError> {
         if address >= self.data.len() {
-            Err(format!("Memory write out of bounds at address: {:04X}", address))
+            Err(EmuError::OutOfBounds { addr: address, kind: AccessKind::Write })
         } else {
             self.data[address] = value;
             Ok(())
@@ -35,9 +48,9 @@ impl Memory {
     }
 
     /// Read a 16-bit value (2 bytes) from a certain address
-    pub fn read_u16(&self, address: usize) -> Result<u16, String> {
+    pub fn read_u16(&self, address: usize) -> Result<u16, EmuError> {
         if address + 1 >= self.data.len() {
-            Err(format!("Memory read_u16 out of bounds at address: {:04X}", address))
+            Err(EmuError::OutOfBounds { addr: address, kind: AccessKind::Read })
         } else {
             let low = self.data[address] as u16; // Read the lower 8 bits
             let high = self.data[address + 1] as u16; // Read the upper 8 bits
@@ -46,9 +59,9 @@ impl Memory {
     }
 
     /// Write a 16-bit value (2 bytes) to a certain address
-    pub fn write_u16(&mut self, address: usize, value: u16) -> Result<(), String> {
+    pub fn write_u16(&mut self, address: usize, value: u16) -> Result<(), EmuError> {
         if address + 1 >= self.data.len() {
-            Err(format!("Memory write_u16 out of bounds at address: {:04X}", address))
+            Err(EmuError::OutOfBounds { addr: address, kind: AccessKind::Write })
         } else {
             let low = (value & 0x00FF) as u8; // Extract the lower 8 bits
             let high = ((value >> 8) & 0x00FF) as u8; // Extract the upper 8 bits
@@ -0,0 +1,293 @@
+use std::path::Path;
+
+use crate::assembler::parse_hex_default;
+use crate::bus::Bus;
+use crate::error::{AccessKind, EmuError};
+
+/// What happens to CPU writes into a ROM region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePolicy {
+    Reject, // Fail the write with `EmuError::WriteToRom`
+    Ignore, // Drop the write silently, as real ROM does
+}
+
+/// Kind of an address range in the map
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegionKind {
+    Ram,
+    Rom(WritePolicy),
+    Mirror { target: u16 }, // Repeats the RAM or ROM region that contains `target`
+    Unmapped,               // Reads return the open-bus value, writes are dropped
+}
+
+/// One contiguous address range, `start..=end`
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub start: u16,
+    pub end: u16,
+    pub kind: RegionKind,
+    data: Vec<u8>, // Backing store for RAM and ROM regions
+}
+
+impl Region {
+    /// Number of addresses covered
+    pub fn size(&self) -> usize {
+        self.end as usize - self.start as usize + 1
+    }
+
+    /// Whether `address` falls inside the region
+    pub fn contains(&self, address: usize) -> bool {
+        (self.start as usize..=self.end as usize).contains(&address)
+    }
+
+    /// Contents of a RAM or ROM region (empty for mirrors and unmapped ranges)
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// 64K address space assembled from RAM, ROM, mirrored and unmapped regions.
+///
+/// Addresses not covered by any region behave as unmapped.
+#[derive(Debug, Clone)]
+pub struct MemoryMap {
+    regions: Vec<Region>,
+    pub open_bus: u8, // Value read from unmapped addresses
+}
+
+/// Where an address resolves to after following mirrors
+enum Target {
+    Byte { region: usize, offset: usize },
+    Unmapped,
+}
+
+/// Layout of a 32K MicroBee: RAM, BASIC ROM, optional ROM sockets, the
+/// network/monitor ROM and the screen and character generator RAM at the top.
+pub const MICROBEE_32K: &str = "\
+# MicroBee 32K
+0000-7FFF ram
+8000-BFFF rom             # BASIC
+C000-DFFF rom             # Edasm / WordBee socket
+E000-EFFF rom             # Network / monitor
+F000-F7FF ram             # Screen RAM
+F800-FFFF ram             # Programmable character generator
+";
+
+/// Built-in maps `--memory-map` accepts by name
+pub const PRESETS: &[(&str, &str)] = &[("microbee32k", MICROBEE_32K)];
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryMap {
+    /// Create a map with nothing mapped
+    pub fn new() -> Self {
+        MemoryMap {
+            regions: Vec::new(),
+            open_bus: 0xFF,
+        }
+    }
+
+    /// Add a region covering `start..=end`; RAM is zeroed and ROM reads as 0xFF until loaded
+    pub fn add(&mut self, start: u16, end: u16, kind: RegionKind) -> Result<(), String> {
+        if end < start {
+            return Err(format!("region {:04X}-{:04X} ends before it starts", start, end));
+        }
+        if let Some(other) = self
+            .regions
+            .iter()
+            .find(|region| region.start <= end && start <= region.end)
+        {
+            return Err(format!(
+                "region {:04X}-{:04X} overlaps {:04X}-{:04X}",
+                start, end, other.start, other.end
+            ));
+        }
+        let len = end as usize - start as usize + 1;
+        let data = match kind {
+            RegionKind::Ram => vec![0; len],
+            RegionKind::Rom(_) => vec![0xFF; len],
+            RegionKind::Mirror { .. } | RegionKind::Unmapped => Vec::new(),
+        };
+        self.regions.push(Region { start, end, kind, data });
+        self.regions.sort_by_key(|region| region.start);
+        Ok(())
+    }
+
+    /// All regions in address order
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// Parse a map description, one region per line:
+    ///
+    /// ```text
+    /// START-END ram
+    /// START-END rom [FILE] [reject|ignore]   # writes are rejected unless `ignore` is given
+    /// START-END mirror TARGET
+    /// START-END unmapped
+    /// open-bus VALUE
+    /// ```
+    ///
+    /// Addresses are hex. ROM files are read relative to `base_dir` and must
+    /// not be larger than their region. `#` starts a comment.
+    pub fn parse(description: &str, base_dir: &Path) -> Result<Self, String> {
+        let mut map = MemoryMap::new();
+        for (index, line) in description.lines().enumerate() {
+            let words: Vec<&str> = line
+                .split('#')
+                .next()
+                .unwrap_or_default()
+                .split_whitespace()
+                .collect();
+            map.parse_line(&words, base_dir)
+                .map_err(|err| format!("line {}: {}", index + 1, err))?;
+        }
+        map.check_mirrors()?;
+        Ok(map)
+    }
+
+    fn parse_line(&mut self, words: &[&str], base_dir: &Path) -> Result<(), String> {
+        let Some((&range, args)) = words.split_first() else {
+            return Ok(());
+        };
+        if range.eq_ignore_ascii_case("open-bus") {
+            let [value] = args else {
+                return Err("usage: open-bus VALUE".to_string());
+            };
+            self.open_bus = u8::try_from(parse_hex(value)?).map_err(|_| format!("bad byte '{}'", value))?;
+            return Ok(());
+        }
+
+        let (start, end) = range
+            .split_once('-')
+            .ok_or_else(|| format!("expected START-END, found '{}'", range))?;
+        let (start, end) = (parse_hex(start)?, parse_hex(end)?);
+        let (kind, args) = args.split_first().ok_or("missing region kind")?;
+        match (kind.to_ascii_lowercase().as_str(), args) {
+            ("ram", []) => self.add(start, end, RegionKind::Ram),
+            ("unmapped", []) => self.add(start, end, RegionKind::Unmapped),
+            ("mirror", [target]) => self.add(start, end, RegionKind::Mirror { target: parse_hex(target)? }),
+            ("rom", args) => {
+                let mut policy = WritePolicy::Reject;
+                let mut file = None;
+                for arg in args {
+                    match arg.to_ascii_lowercase().as_str() {
+                        "reject" => policy = WritePolicy::Reject,
+                        "ignore" => policy = WritePolicy::Ignore,
+                        _ if file.is_none() => file = Some(base_dir.join(arg)),
+                        _ => return Err(format!("unexpected '{}'", arg)),
+                    }
+                }
+                self.add(start, end, RegionKind::Rom(policy))?;
+                if let Some(path) = file {
+                    let bytes = std::fs::read(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+                    if bytes.len() > end as usize - start as usize + 1 {
+                        return Err(format!("{} does not fit in {:04X}-{:04X}", path.display(), start, end));
+                    }
+                    self.load(start as usize, &bytes).map_err(|err| err.to_string())?;
+                }
+                Ok(())
+            }
+            (other, _) => Err(format!("unknown region kind '{}' or wrong arguments", other)),
+        }
+    }
+
+    /// Every mirror must point into a RAM or ROM region
+    fn check_mirrors(&self) -> Result<(), String> {
+        for region in &self.regions {
+            if let RegionKind::Mirror { target } = region.kind {
+                let backed = self.regions.iter().any(|other| {
+                    other.contains(target as usize) && matches!(other.kind, RegionKind::Ram | RegionKind::Rom(_))
+                });
+                if !backed {
+                    return Err(format!(
+                        "mirror {:04X}-{:04X} targets {:04X}, which is not RAM or ROM",
+                        region.start, region.end, target
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve(&self, address: usize) -> Target {
+        let Some(index) = self.regions.iter().position(|region| region.contains(address)) else {
+            return Target::Unmapped;
+        };
+        let region = &self.regions[index];
+        let offset = address - region.start as usize;
+        match region.kind {
+            RegionKind::Ram | RegionKind::Rom(_) => Target::Byte { region: index, offset },
+            RegionKind::Mirror { target } => {
+                // Wrap the offset within the mirrored region so small regions repeat
+                match self.regions.iter().position(|other| other.contains(target as usize)) {
+                    Some(backing) if !matches!(self.regions[backing].kind, RegionKind::Mirror { .. }) => {
+                        let base = target as usize - self.regions[backing].start as usize;
+                        let len = self.regions[backing].size() - base;
+                        Target::Byte {
+                            region: backing,
+                            offset: base + offset % len,
+                        }
+                    }
+                    _ => Target::Unmapped,
+                }
+            }
+            RegionKind::Unmapped => Target::Unmapped,
+        }
+    }
+}
+
+impl Bus for MemoryMap {
+    fn read(&mut self, address: usize) -> Result<u8, EmuError> {
+        self.peek(address)
+    }
+
+    fn write(&mut self, address: usize, value: u8) -> Result<(), EmuError> {
+        if address > 0xFFFF {
+            return Err(EmuError::OutOfBounds { addr: address, kind: AccessKind::Write });
+        }
+        if let Target::Byte { region, offset } = self.resolve(address) {
+            let region = &mut self.regions[region];
+            match region.kind {
+                RegionKind::Rom(WritePolicy::Reject) => return Err(EmuError::WriteToRom { addr: address }),
+                RegionKind::Rom(WritePolicy::Ignore) => {}
+                _ => region.data[offset] = value,
+            }
+        }
+        Ok(())
+    }
+
+    fn peek(&self, address: usize) -> Result<u8, EmuError> {
+        if address > 0xFFFF {
+            return Err(EmuError::OutOfBounds { addr: address, kind: AccessKind::Read });
+        }
+        Ok(match self.resolve(address) {
+            Target::Byte { region, offset } => self.regions[region].data[offset],
+            Target::Unmapped => self.open_bus,
+        })
+    }
+
+    /// Unlike CPU writes, loading also fills ROM regions, so images can be placed in ROM
+    fn load(&mut self, address: usize, bytes: &[u8]) -> Result<(), EmuError> {
+        for (offset, &byte) in bytes.iter().enumerate() {
+            let address = address + offset;
+            if address > 0xFFFF {
+                return Err(EmuError::OutOfBounds { addr: address, kind: AccessKind::Write });
+            }
+            if let Target::Byte { region, offset } = self.resolve(address) {
+                self.regions[region].data[offset] = byte;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_hex(text: &str) -> Result<u16, String> {
+    parse_hex_default(text)
+        .and_then(|value| u16::try_from(value).ok())
+        .ok_or_else(|| format!("bad address '{}'", text))
+}
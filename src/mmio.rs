@@ -0,0 +1,150 @@
+use std::ops::Range;
+
+use crate::banked_memory::BankMapping;
+use crate::bus::Bus;
+use crate::devices::Device;
+use crate::error::EmuError;
+use crate::memory::Memory;
+
+/// A device and the addresses it answers
+struct Mapping {
+    range: Range<usize>,
+    device: Box<dyn Device>,
+}
+
+/// Bus that routes registered address ranges to devices and everything
+/// else to an inner bus (flat memory, a memory map or banked memory).
+pub struct MmioBus<B = Memory> {
+    inner: B,
+    mappings: Vec<Mapping>, // Kept sorted by start address
+}
+
+impl<B: Bus> MmioBus<B> {
+    /// Wrap `inner` with no devices mapped
+    pub fn new(inner: B) -> Self {
+        MmioBus {
+            inner,
+            mappings: Vec::new(),
+        }
+    }
+
+    /// Route accesses in `range` to `device` instead of the inner bus
+    pub fn map_io(&mut self, range: Range<usize>, device: Box<dyn Device>) -> Result<(), String> {
+        if range.is_empty() || range.end > self.inner.size() {
+            return Err(format!("cannot map a device at {:04X}..{:04X}", range.start, range.end));
+        }
+        if let Some(other) = self
+            .mappings
+            .iter()
+            .find(|mapping| mapping.range.start < range.end && range.start < mapping.range.end)
+        {
+            return Err(format!(
+                "{:04X}..{:04X} overlaps the device at {:04X}..{:04X}",
+                range.start, range.end, other.range.start, other.range.end
+            ));
+        }
+        self.mappings.push(Mapping { range, device });
+        self.mappings.sort_by_key(|mapping| mapping.range.start);
+        Ok(())
+    }
+
+    /// Address ranges that have devices mapped, in address order
+    pub fn io_ranges(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        self.mappings.iter().map(|mapping| mapping.range.clone())
+    }
+
+    /// The bus behind the devices
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// The bus behind the devices, mutably
+    pub fn inner_mut(&mut self) -> &mut B {
+        &mut self.inner
+    }
+
+    fn device_at(&self, address: usize) -> Option<usize> {
+        self.mappings.iter().position(|mapping| mapping.range.contains(&address))
+    }
+}
+
+impl<B: Bus> Bus for MmioBus<B> {
+    fn read(&mut self, address: usize) -> Result<u8, EmuError> {
+        match self.device_at(address) {
+            Some(index) => {
+                let mapping = &mut self.mappings[index];
+                mapping
+                    .device
+                    .read(address - mapping.range.start)
+                    .map_err(|message| EmuError::BusFault { addr: address, message })
+            }
+            None => self.inner.read(address),
+        }
+    }
+
+    fn write(&mut self, address: usize, value: u8) -> Result<(), EmuError> {
+        match self.device_at(address) {
+            Some(index) => {
+                let mapping = &mut self.mappings[index];
+                mapping
+                    .device
+                    .write(address - mapping.range.start, value)
+                    .map_err(|message| EmuError::BusFault { addr: address, message })
+            }
+            None => self.inner.write(address, value),
+        }
+    }
+
+    fn peek(&self, address: usize) -> Result<u8, EmuError> {
+        match self.device_at(address) {
+            Some(index) => {
+                let mapping = &self.mappings[index];
+                Ok(mapping.device.peek(address - mapping.range.start))
+            }
+            None => self.inner.peek(address),
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.inner.size()
+    }
+
+    /// Bytes landing on a device are written to it; the rest go to the inner bus's `load`
+    fn load(&mut self, address: usize, bytes: &[u8]) -> Result<(), EmuError> {
+        let mut start = 0;
+        while start < bytes.len() {
+            let here = address + start;
+            if self.device_at(here).is_some() {
+                self.write(here, bytes[start])?;
+                start += 1;
+                continue;
+            }
+            // Hand the inner bus everything up to the next device in one call
+            let next_device = self
+                .mappings
+                .iter()
+                .map(|mapping| mapping.range.start)
+                .find(|&device_start| device_start > here)
+                .map_or(bytes.len(), |device_start| (device_start - address).min(bytes.len()));
+            self.inner.load(here, &bytes[start..next_device])?;
+            start = next_device;
+        }
+        Ok(())
+    }
+
+    fn banks(&self) -> Vec<BankMapping> {
+        self.inner.banks()
+    }
+
+    fn write_port(&mut self, port: u8, value: u8) -> bool {
+        self.inner.write_port(port, value)
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.inner.tick(cycles);
+        for mapping in &mut self.mappings {
+            mapping.device.tick(cycles);
+            mapping.device.dma(&mut self.inner);
+        }
+    }
+}
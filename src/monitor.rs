@@ -0,0 +1,392 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Write};
+
+use crate::assembler;
+use crate::bus::Bus;
+use crate::cpu::{StepOutcome, CPU};
+use crate::disassembler;
+use crate::error::EmuError;
+use crate::memory::Memory;
+
+/// Instructions `go` executes before returning to the prompt if nothing stops it
+pub const RUN_LIMIT: u64 = 10_000_000;
+
+/// Most instructions one `s` or `u` command lists; use breakpoints and `g` for longer runs
+pub const LIST_LIMIT: usize = 0x1000;
+
+const HELP: &str = "\
+Commands (numbers are hex unless prefixed with $, 0x or %; labels may be used for addresses):
+  s [n]              step n instructions (default 1, at most 1000)
+  g [addr]           go from addr (default PC) until a breakpoint, HALT or error
+  b [addr]           set a breakpoint, or list breakpoints
+  bc [addr]          clear a breakpoint, or all breakpoints
+  r [reg value]      show registers, or set pc/sp/acc/a/b/flags/ie
+  d [addr] [len]     hex dump memory (default PC, 64 bytes)
+  e addr [bytes..]   examine a byte, or write bytes starting at addr
+  u [addr] [count]   disassemble (default around PC, 12 instructions, at most 1000)
+  f start end value  fill start..=end with value
+  m start end dest   move (copy) start..=end to dest
+  i port             read an I/O port without side effects
+  o port value       write an I/O port as OUT does
+  k                  show which physical banks are mapped into each window
+  q                  quit";
+
+/// What the REPL should do after a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+/// Interactive monitor in the style of the MicroBee's built-in monitor
+pub struct Monitor<B = Memory> {
+    pub cpu: CPU<B>, // Breakpoints live in `cpu.breakpoints`
+    pub symbols: BTreeMap<String, u16>, // Labels usable wherever an address is expected
+}
+
+impl<B: Bus> Monitor<B> {
+    /// Create a monitor controlling `cpu`
+    pub fn new(cpu: CPU<B>) -> Self {
+        Monitor {
+            cpu,
+            symbols: BTreeMap::new(),
+        }
+    }
+
+    /// Read commands from `input` until `q` or end of input
+    pub fn repl(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        writeln!(output, "{}", self.cpu.format_registers())?;
+        write!(output, "> ")?;
+        output.flush()?;
+        for line in input.lines() {
+            let mut text = String::new();
+            let flow = match self.command(&line?, &mut text) {
+                Ok(flow) => flow,
+                Err(err) => {
+                    let _ = writeln!(text, "? {}", err);
+                    Flow::Continue
+                }
+            };
+            write!(output, "{}", text)?;
+            if flow == Flow::Quit {
+                return Ok(());
+            }
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Execute one command line, appending anything it prints to `out`
+    pub fn command(&mut self, line: &str, out: &mut String) -> Result<Flow, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&name, args)) = words.split_first() else {
+            return Ok(Flow::Continue);
+        };
+        match name.to_ascii_lowercase().as_str() {
+            "s" | "t" => {
+                let count = match args.first() {
+                    Some(text) => self.count(text)?,
+                    None => 1,
+                };
+                for _ in 0..count {
+                    if !self.cpu.halted && !self.cpu.waiting {
+                        self.list_instruction(self.cpu.pc, out);
+                    }
+                    match self.cpu.step() {
+                        StepOutcome::Executed { .. } | StepOutcome::Breakpoint => {}
+                        outcome => {
+                            self.report_stop(&outcome, out);
+                            break;
+                        }
+                    }
+                }
+                let _ = writeln!(out, "{}", self.cpu.format_registers());
+            }
+            "g" => {
+                if let Some(text) = args.first() {
+                    self.cpu.pc = self.address(text)?;
+                }
+                self.go(out);
+            }
+            "b" => match args.first() {
+                Some(text) => {
+                    let address = self.address(text)?;
+                    self.cpu.breakpoints.insert(address);
+                }
+                None => {
+                    for &address in &self.cpu.breakpoints {
+                        let _ = writeln!(out, "{:04X}{}", address, self.label_suffix(address));
+                    }
+                }
+            },
+            "bc" => match args.first() {
+                Some(text) => {
+                    let address = self.address(text)?;
+                    if !self.cpu.breakpoints.remove(&address) {
+                        return Err(format!("no breakpoint at {:04X}", address));
+                    }
+                }
+                None => self.cpu.breakpoints.clear(),
+            },
+            "r" => {
+                if let [register, value] = args {
+                    self.set_register(register, value)?;
+                } else if !args.is_empty() {
+                    return Err("usage: r [register value]".to_string());
+                }
+                let _ = writeln!(out, "{}", self.cpu.format_registers());
+            }
+            "d" => {
+                let start = match args.first() {
+                    Some(text) => self.address(text)?,
+                    None => self.cpu.pc,
+                };
+                let length = match args.get(1) {
+                    Some(text) => self.number(text)?,
+                    None => 64,
+                };
+                self.hex_dump(start, length, out)?;
+            }
+            "e" => {
+                let address = self.address(args.first().ok_or("usage: e addr [bytes..]")?)?;
+                if args.len() == 1 {
+                    let value = self.cpu.memory.peek(address as usize).map_err(|err| err.to_string())?;
+                    let _ = writeln!(out, "{:04X}: {:02X}", address, value);
+                }
+                for (offset, text) in args[1..].iter().enumerate() {
+                    let value = self.byte(text)?;
+                    self.cpu
+                        .memory
+                        .write(address as usize + offset, value)
+                        .map_err(|err| err.to_string())?;
+                }
+            }
+            "u" => {
+                let count = match args.get(1) {
+                    Some(text) => self.count(text)?,
+                    None => 12,
+                };
+                let start = match args.first() {
+                    Some(text) => self.address(text)?,
+                    None => self.start_before(self.cpu.pc, 4),
+                };
+                let mut address = start;
+                for _ in 0..count {
+                    address = self.list_instruction(address, out);
+                }
+            }
+            "f" => {
+                let [start, end, value] = args else {
+                    return Err("usage: f start end value".to_string());
+                };
+                let (start, end) = (self.address(start)?, self.address(end)?);
+                let value = self.byte(value)?;
+                for address in start..=end {
+                    self.cpu.memory.write(address as usize, value).map_err(|err| err.to_string())?;
+                }
+            }
+            "m" => {
+                let [start, end, dest] = args else {
+                    return Err("usage: m start end dest".to_string());
+                };
+                let (start, end, dest) = (self.address(start)?, self.address(end)?, self.address(dest)?);
+                let block = (start..=end)
+                    .map(|address| self.cpu.memory.peek(address as usize))
+                    .collect::<Result<Vec<u8>, EmuError>>()
+                    .map_err(|err| err.to_string())?;
+                // Copied out first so overlapping moves work; writes obey ROM protection as `e` and `f` do
+                for (offset, &value) in block.iter().enumerate() {
+                    self.cpu
+                        .memory
+                        .write(dest as usize + offset, value)
+                        .map_err(|err| err.to_string())?;
+                }
+            }
+            "i" => {
+                let port = self.byte(args.first().ok_or("usage: i port")?)?;
+                let _ = writeln!(out, "port {:02X}: {:02X}", port, self.cpu.ports.peek(port));
+            }
+            "o" => {
+                let [port, value] = args else {
+                    return Err("usage: o port value".to_string());
+                };
+                let (port, value) = (self.byte(port)?, self.byte(value)?);
+                self.cpu.port_out(port, value).map_err(|err| err.to_string())?;
+            }
+            "k" => {
+                let banks = self.cpu.memory.banks();
+                if banks.is_empty() {
+                    let _ = writeln!(out, "No bank switching");
+                }
+                for bank in banks {
+                    let _ = writeln!(
+                        out,
+                        "{:04X}-{:04X}  bank {:<3} physical {:05X}",
+                        bank.cpu_start, bank.cpu_end, bank.bank, bank.physical_start
+                    );
+                }
+            }
+            "h" | "?" | "help" => {
+                let _ = writeln!(out, "{}", HELP);
+            }
+            "q" | "quit" => return Ok(Flow::Quit),
+            other => return Err(format!("unknown command '{}', try h", other)),
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// Run until a breakpoint, HALT, an error or `RUN_LIMIT` steps
+    fn go(&mut self, out: &mut String) {
+        let mut executed = 0u64;
+        let outcome = self.cpu.run_until(|_| {
+            executed += 1;
+            executed >= RUN_LIMIT
+        });
+        match outcome {
+            StepOutcome::Executed { .. } | StepOutcome::WaitingForInterrupt => {
+                let _ = writeln!(out, "Stopped after {} steps", executed);
+            }
+            outcome => self.report_stop(&outcome, out),
+        }
+        let _ = writeln!(out, "{}", self.cpu.format_registers());
+    }
+
+    /// Explain why execution stopped, listing the faulting instruction when known
+    fn report_stop(&self, outcome: &StepOutcome, out: &mut String) {
+        match outcome {
+            StepOutcome::Breakpoint => {
+                let _ = writeln!(out, "Breakpoint at {:04X}{}", self.cpu.pc, self.label_suffix(self.cpu.pc));
+            }
+            StepOutcome::Halted => {
+                let _ = writeln!(out, "Halted");
+            }
+            StepOutcome::WaitingForInterrupt => {
+                let _ = writeln!(out, "Waiting for interrupt");
+            }
+            StepOutcome::Trapped(err) => {
+                let _ = writeln!(out, "Trap: {}", err);
+                if let EmuError::UnknownOpcode { pc, .. } | EmuError::DivideByZero { pc } = err {
+                    self.list_instruction(*pc, out);
+                }
+            }
+            StepOutcome::Executed { .. } => {}
+        }
+    }
+
+    fn set_register(&mut self, register: &str, value: &str) -> Result<(), String> {
+        let value = self.address(value)?;
+        let byte = || u8::try_from(value).map_err(|_| format!("{:X} does not fit in 8 bits", value));
+        match register.to_ascii_lowercase().as_str() {
+            "pc" => self.cpu.pc = value,
+            "sp" => self.cpu.sp = value,
+            "acc" => self.cpu.acc = byte()?,
+            "a" => self.cpu.reg_a = byte()?,
+            "b" => self.cpu.reg_b = byte()?,
+            "flags" | "f" => self.cpu.flags = byte()?,
+            "ie" => self.cpu.interrupts_enabled = value != 0,
+            other => return Err(format!("unknown register '{}'", other)),
+        }
+        Ok(())
+    }
+
+    /// Print 16 bytes per row with an ASCII column
+    fn hex_dump(&self, start: u16, length: usize, out: &mut String) -> Result<(), String> {
+        let end = (start as usize + length).min(self.cpu.memory.size());
+        for row in (start as usize..end).step_by(16) {
+            let bytes = (row..(row + 16).min(end))
+                .map(|address| self.cpu.memory.peek(address))
+                .collect::<Result<Vec<u8>, EmuError>>()
+                .map_err(|err| err.to_string())?;
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let ascii: String = bytes
+                .iter()
+                .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+                .collect();
+            let _ = writeln!(out, "{:04X}  {:<47}  {}", row, hex.join(" "), ascii);
+        }
+        Ok(())
+    }
+
+    /// Print the instruction at `address` and return the address of the next one
+    fn list_instruction(&self, address: u16, out: &mut String) -> u16 {
+        for (name, _) in self.symbols.iter().filter(|(_, value)| **value == address) {
+            let _ = writeln!(out, "{}:", name);
+        }
+        let marker = match (address == self.cpu.pc, self.cpu.breakpoints.contains(&address)) {
+            (true, true) => ">*",
+            (true, false) => "> ",
+            (false, true) => " *",
+            (false, false) => "  ",
+        };
+        match disassembler::decode(&self.cpu.memory, address) {
+            Ok(instruction) => {
+                let _ = writeln!(out, "{}{}", marker, instruction);
+                instruction.next_address()
+            }
+            Err(err) => {
+                let _ = writeln!(out, "{}{:04X}  ?? {}", marker, address, err);
+                address.wrapping_add(1)
+            }
+        }
+    }
+
+    /// Find an address up to `instructions` instructions before `target` whose decoding lands on it
+    fn start_before(&self, target: u16, instructions: usize) -> u16 {
+        for back in (1..=instructions as u16 * 3).rev() {
+            let Some(start) = target.checked_sub(back) else {
+                continue;
+            };
+            let mut address = start;
+            let mut decoded = 0;
+            while address < target {
+                match disassembler::decode(&self.cpu.memory, address) {
+                    Ok(instruction) => address = instruction.next_address(),
+                    Err(_) => break,
+                }
+                decoded += 1;
+            }
+            if address == target && decoded <= instructions {
+                return start;
+            }
+        }
+        target
+    }
+
+    fn label_suffix(&self, address: u16) -> String {
+        self.symbols
+            .iter()
+            .find(|(_, value)| **value == address)
+            .map(|(name, _)| format!(" ({})", name))
+            .unwrap_or_default()
+    }
+
+    /// Resolve a label or a hex address
+    fn address(&self, text: &str) -> Result<u16, String> {
+        if let Some(&value) = self.symbols.get(text) {
+            return Ok(value);
+        }
+        u16::try_from(self.number(text)?).map_err(|_| format!("address out of range: {}", text))
+    }
+
+    fn byte(&self, text: &str) -> Result<u8, String> {
+        u8::try_from(self.number(text)?).map_err(|_| format!("byte out of range: {}", text))
+    }
+
+    /// Parse a repeat count for `s` or `u`, up to `LIST_LIMIT`
+    fn count(&self, text: &str) -> Result<usize, String> {
+        let count = self.number(text)?;
+        if count > LIST_LIMIT {
+            return Err(format!("count {:X} is above the limit of {:X}", count, LIST_LIMIT));
+        }
+        Ok(count)
+    }
+
+    /// Parse a number, hex by default as on the MicroBee monitor
+    fn number(&self, text: &str) -> Result<usize, String> {
+        assembler::parse_hex_default(text)
+            .and_then(|value| usize::try_from(value).ok())
+            .ok_or_else(|| format!("bad number '{}'", text))
+    }
+}
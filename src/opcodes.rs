@@ -0,0 +1,142 @@
+/// Kind of operand that follows an opcode byte
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    None,      // Opcode only
+    Immediate, // One byte value
+    Address,   // Two byte address, little-endian
+}
+
+impl Operand {
+    /// Number of operand bytes following the opcode
+    pub fn size(self) -> usize {
+        match self {
+            Operand::None => 0,
+            Operand::Immediate => 1,
+            Operand::Address => 2,
+        }
+    }
+}
+
+/// One entry of the instruction set
+#[derive(Debug)]
+pub struct OpInfo {
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub operand: Operand,
+    pub cycles: u32, // Base cost, before MEMORY_ACCESS_CYCLES and BRANCH_TAKEN_CYCLES
+}
+
+impl OpInfo {
+    /// Total instruction length in bytes, including the opcode
+    pub fn size(&self) -> usize {
+        1 + self.operand.size()
+    }
+}
+
+pub const LOAD: u8 = 0x01;
+pub const STORE: u8 = 0x02;
+pub const ADD: u8 = 0x03;
+pub const SUB: u8 = 0x04;
+pub const ADC: u8 = 0x05;
+pub const SBC: u8 = 0x06;
+pub const INC: u8 = 0x07;
+pub const DEC: u8 = 0x08;
+pub const AND: u8 = 0x09;
+pub const OR: u8 = 0x0A;
+pub const XOR: u8 = 0x0B;
+pub const JMP: u8 = 0x10;
+pub const JZ: u8 = 0x11;
+pub const JNZ: u8 = 0x12;
+pub const LDA: u8 = 0x13;
+pub const MOV: u8 = 0x14;
+pub const MUL: u8 = 0x15;
+pub const DIV: u8 = 0x16;
+pub const CMP: u8 = 0x17;
+pub const CALL: u8 = 0x18;
+pub const RET: u8 = 0x19;
+pub const JP: u8 = 0x1A;
+pub const JN: u8 = 0x1B;
+pub const INT: u8 = 0x1C;
+pub const CLI: u8 = 0x1D;
+pub const SEI: u8 = 0x1E;
+pub const PUSH: u8 = 0x1F;
+pub const POP: u8 = 0x20;
+pub const JC: u8 = 0x21;
+pub const JNC: u8 = 0x22;
+pub const JLT: u8 = 0x23;
+pub const JGE: u8 = 0x24;
+pub const RETI: u8 = 0x25;
+pub const WAIT: u8 = 0x26;
+pub const IN: u8 = 0x27;
+pub const OUT: u8 = 0x28;
+pub const HALT: u8 = 0xFF;
+
+/// Extra cycles when an instruction reads or writes the byte at its address operand
+pub const MEMORY_ACCESS_CYCLES: u32 = 3;
+
+/// Extra cycles when a conditional jump is taken
+pub const BRANCH_TAKEN_CYCLES: u32 = 3;
+
+/// Cycles to push state and vector to a hardware interrupt handler
+pub const INTERRUPT_CYCLES: u32 = 19;
+
+/// Cycles that pass for each step spent waiting in WAIT
+pub const WAIT_IDLE_CYCLES: u32 = 4;
+
+const fn op(opcode: u8, mnemonic: &'static str, operand: Operand, cycles: u32) -> OpInfo {
+    OpInfo { opcode, mnemonic, operand, cycles }
+}
+
+/// Every instruction understood by `CPU::execute`, with Z80-like base costs in clock cycles:
+/// 4 for register-only work, 7 to fetch an immediate or test a branch, 10 to fetch an address.
+pub const OPCODES: &[OpInfo] = &[
+    op(LOAD, "LOAD", Operand::Address, 10),
+    op(STORE, "STORE", Operand::Address, 10),
+    op(ADD, "ADD", Operand::Address, 10),
+    op(SUB, "SUB", Operand::Address, 10),
+    op(ADC, "ADC", Operand::Address, 10),
+    op(SBC, "SBC", Operand::Address, 10),
+    op(INC, "INC", Operand::None, 4),
+    op(DEC, "DEC", Operand::None, 4),
+    op(AND, "AND", Operand::Address, 10),
+    op(OR, "OR", Operand::Address, 10),
+    op(XOR, "XOR", Operand::Address, 10),
+    op(JMP, "JMP", Operand::Address, 10),
+    op(JZ, "JZ", Operand::Address, 7),
+    op(JNZ, "JNZ", Operand::Address, 7),
+    op(LDA, "LDA", Operand::Immediate, 7),
+    op(MOV, "MOV", Operand::None, 4),
+    op(MUL, "MUL", Operand::None, 12),
+    op(DIV, "DIV", Operand::None, 20),
+    op(CMP, "CMP", Operand::Address, 10),
+    op(CALL, "CALL", Operand::Address, 17),
+    op(RET, "RET", Operand::None, 10),
+    op(JP, "JP", Operand::Address, 7),
+    op(JN, "JN", Operand::Address, 7),
+    op(INT, "INT", Operand::Address, 20),
+    op(CLI, "CLI", Operand::None, 4),
+    op(SEI, "SEI", Operand::None, 4),
+    op(PUSH, "PUSH", Operand::None, 11),
+    op(POP, "POP", Operand::None, 10),
+    op(JC, "JC", Operand::Address, 7),
+    op(JNC, "JNC", Operand::Address, 7),
+    op(JLT, "JLT", Operand::Address, 7),
+    op(JGE, "JGE", Operand::Address, 7),
+    op(RETI, "RETI", Operand::None, 14),
+    op(WAIT, "WAIT", Operand::None, 4),
+    op(IN, "IN", Operand::Immediate, 11),
+    op(OUT, "OUT", Operand::Immediate, 11),
+    op(HALT, "HALT", Operand::None, 4),
+];
+
+/// Look up an instruction by opcode byte
+pub fn lookup(opcode: u8) -> Option<&'static OpInfo> {
+    OPCODES.iter().find(|info| info.opcode == opcode)
+}
+
+/// Look up an instruction by mnemonic (case-insensitive)
+pub fn find(mnemonic: &str) -> Option<&'static OpInfo> {
+    OPCODES
+        .iter()
+        .find(|info| info.mnemonic.eq_ignore_ascii_case(mnemonic))
+}
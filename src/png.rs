@@ -0,0 +1,309 @@
+use std::io::{self, Write};
+
+/// Write 8-bit RGB pixels, row-major from the top-left corner, as a PNG file.
+///
+/// Rows use the Sub filter and are compressed with fixed-Huffman deflate,
+/// matching runs against the previous pixel and the row above, which keeps
+/// mostly blank text screens small without a general-purpose compressor.
+pub fn write_rgb(mut out: impl Write, width: usize, height: usize, pixels: &[u8]) -> io::Result<()> {
+    let stride = width * 3;
+    if pixels.len() != stride * height {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "pixel buffer does not match image size"));
+    }
+    let (w, h) = match (u32::try_from(width), u32::try_from(height)) {
+        (Ok(w), Ok(h)) if w > 0 && h > 0 => (w, h),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "bad image size")),
+    };
+
+    let mut filtered = Vec::with_capacity((stride + 1) * height);
+    for row in pixels.chunks(stride) {
+        filtered.push(1); // Sub: each byte minus the same channel of the pixel to its left
+        filtered.extend(row.iter().enumerate().map(|(i, &byte)| {
+            let left = if i >= 3 { row[i - 3] } else { 0 };
+            byte.wrapping_sub(left)
+        }));
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&w.to_be_bytes());
+    header.extend_from_slice(&h.to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]); // 8 bits per channel, RGB, deflate, no interlace
+
+    out.write_all(b"\x89PNG\r\n\x1a\n")?;
+    write_chunk(&mut out, b"IHDR", &header)?;
+    write_chunk(&mut out, b"IDAT", &zlib(&filtered, stride + 1))?;
+    write_chunk(&mut out, b"IEND", &[])
+}
+
+fn write_chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let crc = crc32(&[kind.as_slice(), data]);
+    out.write_all(&crc.to_be_bytes())
+}
+
+fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in parts.iter().flat_map(|part| part.iter()) {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+/// Match lengths and distances from the deflate specification (RFC 1951)
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+const MAX_MATCH: usize = 258;
+const WINDOW: usize = 32768;
+
+/// Compress `data` into a zlib stream holding a single fixed-Huffman block
+fn zlib(data: &[u8], row_size: usize) -> Vec<u8> {
+    let mut bits = BitWriter::default();
+    bits.write(1, 1); // Final block
+    bits.write(1, 2); // Fixed Huffman codes
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let best = [1, 3, row_size]
+            .into_iter()
+            .filter(|&distance| distance <= pos && distance <= WINDOW)
+            .map(|distance| {
+                let limit = (data.len() - pos).min(MAX_MATCH);
+                let length = (0..limit).take_while(|&i| data[pos + i] == data[pos + i - distance]).count();
+                (length, distance)
+            })
+            .max();
+        match best {
+            Some((length, distance)) if length >= 3 => {
+                bits.write_length(length);
+                bits.write_distance(distance);
+                pos += length;
+            }
+            _ => {
+                bits.write_symbol(data[pos] as u16);
+                pos += 1;
+            }
+        }
+    }
+    bits.write_symbol(256); // End of block
+
+    let mut stream = vec![0x78, 0x01];
+    stream.extend_from_slice(&bits.finish());
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+/// Deflate bit stream: values are packed least significant bit first
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, bits: u32) {
+        self.buffer |= value << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are defined most significant bit first, so reverse them
+    fn write_code(&mut self, code: u32, bits: u32) {
+        self.write(code.reverse_bits() >> (32 - bits), bits);
+    }
+
+    /// A literal/length symbol using the fixed code table
+    fn write_symbol(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol, 8),
+            144..=255 => self.write_code(0x190 + symbol - 144, 9),
+            256..=279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0xC0 + symbol - 280, 8),
+        }
+    }
+
+    fn write_length(&mut self, length: usize) {
+        let index = LENGTH_BASE.iter().rposition(|&base| base as usize <= length).unwrap_or(0);
+        self.write_symbol(257 + index as u16);
+        self.write((length - LENGTH_BASE[index] as usize) as u32, LENGTH_EXTRA[index] as u32);
+    }
+
+    fn write_distance(&mut self, distance: usize) {
+        let index = DISTANCE_BASE.iter().rposition(|&base| base as usize <= distance).unwrap_or(0);
+        self.write_code(index as u32, 5);
+        self.write((distance - DISTANCE_BASE[index] as usize) as u32, DISTANCE_EXTRA[index] as u32);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_known_answer() {
+        assert_eq!(crc32(&[b"123456789"]), 0xCBF4_3926);
+        assert_eq!(crc32(&[b"1234", b"56789"]), 0xCBF4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn adler32_known_answer() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(adler32(&[]), 1);
+        assert_eq!(adler32(&[0xFF; 6000]), 0xA497_59EA); // Spans the 5552-byte reduction
+    }
+
+    /// Reads a deflate bit stream least significant bit first
+    struct BitReader<'a> {
+        bytes: &'a [u8],
+        position: usize,
+    }
+
+    impl BitReader<'_> {
+        fn bits(&mut self, count: u32) -> u32 {
+            (0..count).fold(0, |value, bit| {
+                let byte = self.bytes[self.position / 8];
+                let set = (byte >> (self.position % 8)) & 1;
+                self.position += 1;
+                value | (set as u32) << bit
+            })
+        }
+
+        /// A fixed-Huffman literal/length symbol, whose code is sent most significant bit first
+        fn symbol(&mut self) -> u16 {
+            let mut code = (0..7).fold(0, |code, _| code << 1 | self.bits(1));
+            if code <= 0x17 {
+                return 256 + code as u16;
+            }
+            code = code << 1 | self.bits(1);
+            match code {
+                0x30..=0xBF => return (code - 0x30) as u16,
+                0xC0..=0xC7 => return (280 + code - 0xC0) as u16,
+                _ => {}
+            }
+            code = code << 1 | self.bits(1);
+            (144 + code - 0x190) as u16
+        }
+    }
+
+    /// Undo `zlib` for streams made of one fixed-Huffman block
+    fn inflate(stream: &[u8]) -> Vec<u8> {
+        assert_eq!(&stream[..2], [0x78, 0x01]);
+        let mut reader = BitReader { bytes: &stream[2..stream.len() - 4], position: 0 };
+        assert_eq!(reader.bits(3), 0b011); // Final block, fixed codes
+        let mut data: Vec<u8> = Vec::new();
+        loop {
+            let symbol = reader.symbol();
+            match symbol {
+                0..=255 => data.push(symbol as u8),
+                256 => break,
+                _ => {
+                    let index = (symbol - 257) as usize;
+                    let length = LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index] as u32) as usize;
+                    let index = (0..5).fold(0, |code, _| code << 1 | reader.bits(1)) as usize;
+                    let distance = DISTANCE_BASE[index] as usize + reader.bits(DISTANCE_EXTRA[index] as u32) as usize;
+                    for _ in 0..length {
+                        data.push(data[data.len() - distance]);
+                    }
+                }
+            }
+        }
+        assert_eq!(stream[stream.len() - 4..], adler32(&data).to_be_bytes());
+        data
+    }
+
+    /// Split a PNG file into its chunks, checking the signature and every CRC
+    fn chunks(file: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        assert_eq!(&file[..8], b"\x89PNG\r\n\x1a\n");
+        let mut chunks = Vec::new();
+        let mut rest = &file[8..];
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let kind: [u8; 4] = rest[4..8].try_into().unwrap();
+            let data = &rest[8..8 + length];
+            let crc = u32::from_be_bytes(rest[8 + length..12 + length].try_into().unwrap());
+            assert_eq!(crc, crc32(&[&kind, data]));
+            chunks.push((kind, data));
+            rest = &rest[12 + length..];
+        }
+        chunks
+    }
+
+    #[test]
+    fn small_image_round_trips_through_ihdr_and_idat() {
+        let (width, height) = (5, 3);
+        let mut pixels = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                pixels.extend([(x * 50) as u8, (y * 100) as u8, if x == y { 255 } else { 0 }]);
+            }
+        }
+        pixels[..6].copy_from_slice(&[7, 7, 7, 7, 7, 7]); // A run the compressor matches
+        let mut file = Vec::new();
+        write_rgb(&mut file, width, height, &pixels).unwrap();
+
+        let chunks = chunks(&file);
+        let kinds: Vec<&[u8; 4]> = chunks.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 5, 0, 0, 0, 3, 8, 2, 0, 0, 0]);
+        assert!(chunks[2].1.is_empty());
+
+        let filtered = inflate(chunks[1].1);
+        let stride = width * 3;
+        assert_eq!(filtered.len(), (stride + 1) * height);
+        let mut decoded = Vec::new();
+        for row in filtered.chunks(stride + 1) {
+            assert_eq!(row[0], 1); // Sub filter
+            for (i, &byte) in row[1..].iter().enumerate() {
+                let left = if i >= 3 { decoded[decoded.len() - 3] } else { 0 };
+                decoded.push(byte.wrapping_add(left));
+            }
+        }
+        assert_eq!(decoded, pixels);
+    }
+
+    #[test]
+    fn rejects_mismatched_buffers_and_empty_images() {
+        assert!(write_rgb(Vec::new(), 2, 2, &[0; 11]).is_err());
+        assert!(write_rgb(Vec::new(), 0, 0, &[]).is_err());
+    }
+}
@@ -0,0 +1,179 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::bus::Bus;
+use crate::devices::Device;
+use crate::error::{AccessKind, EmuError};
+
+/// One IN or OUT seen on the port bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortAccess {
+    pub cycle: u64, // CPU cycle count when the instruction started
+    pub port: u8,
+    pub value: u8,
+    pub kind: AccessKind,
+    pub claimed: bool, // Whether a device (or the memory system) answered
+}
+
+impl fmt::Display for PortAccess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, arrow) = match self.kind {
+            AccessKind::Read => ("IN ", "->"),
+            AccessKind::Write => ("OUT", "<-"),
+        };
+        write!(f, "{} port {:02X} {} {:02X} @{}", name, self.port, arrow, self.value, self.cycle)?;
+        if !self.claimed {
+            write!(f, " (unclaimed)")?;
+        }
+        Ok(())
+    }
+}
+
+/// A device and the ports it answers
+struct PortMapping {
+    ports: RangeInclusive<u8>,
+    device: Box<dyn Device>,
+}
+
+/// The 256-port I/O space reached by IN and OUT, separate from memory.
+///
+/// Devices see the port number relative to the first port they are mapped at.
+pub struct PortBus {
+    mappings: Vec<PortMapping>,
+    pub unclaimed: u8,              // Value read from ports no device answers
+    trace: Option<Vec<PortAccess>>, // Recorded traffic while tracing is on
+    cycle: u64,                     // Cycles seen through `tick`, used to stamp the trace
+}
+
+impl Default for PortBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PortBus {
+    /// Create a port bus with nothing attached; unclaimed ports read 0xFF
+    pub fn new() -> Self {
+        PortBus {
+            mappings: Vec::new(),
+            unclaimed: 0xFF,
+            trace: None,
+            cycle: 0,
+        }
+    }
+
+    /// Route `ports` to `device`
+    pub fn map_ports(&mut self, ports: RangeInclusive<u8>, device: Box<dyn Device>) -> Result<(), String> {
+        if ports.is_empty() {
+            return Err(format!("empty port range {:02X}-{:02X}", ports.start(), ports.end()));
+        }
+        if let Some(other) = self
+            .mappings
+            .iter()
+            .find(|mapping| mapping.ports.start() <= ports.end() && ports.start() <= mapping.ports.end())
+        {
+            return Err(format!(
+                "ports {:02X}-{:02X} overlap the device at {:02X}-{:02X}",
+                ports.start(),
+                ports.end(),
+                other.ports.start(),
+                other.ports.end()
+            ));
+        }
+        self.mappings.push(PortMapping { ports, device });
+        self.mappings.sort_by_key(|mapping| *mapping.ports.start());
+        Ok(())
+    }
+
+    /// Port ranges that have devices attached, in port order
+    pub fn port_ranges(&self) -> impl Iterator<Item = RangeInclusive<u8>> + '_ {
+        self.mappings.iter().map(|mapping| mapping.ports.clone())
+    }
+
+    /// Start or stop recording port traffic; stopping discards anything not yet taken
+    pub fn set_trace(&mut self, enabled: bool) {
+        self.trace = enabled.then(Vec::new);
+    }
+
+    /// Hand over the traffic recorded since the last call
+    pub fn take_trace(&mut self) -> Vec<PortAccess> {
+        self.trace.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Read a port as IN does
+    pub fn read(&mut self, port: u8) -> Result<u8, EmuError> {
+        let (value, claimed) = match self.device_at(port) {
+            Some(index) => {
+                let mapping = &mut self.mappings[index];
+                let value = mapping
+                    .device
+                    .read((port - mapping.ports.start()) as usize)
+                    .map_err(|message| EmuError::BusFault { addr: port as usize, message })?;
+                (value, true)
+            }
+            None => (self.unclaimed, false),
+        };
+        self.record(port, value, AccessKind::Read, claimed);
+        Ok(value)
+    }
+
+    /// Write a port as OUT does; writes nobody claims are dropped
+    pub fn write(&mut self, port: u8, value: u8) -> Result<(), EmuError> {
+        let claimed = match self.device_at(port) {
+            Some(index) => {
+                let mapping = &mut self.mappings[index];
+                mapping
+                    .device
+                    .write((port - mapping.ports.start()) as usize, value)
+                    .map_err(|message| EmuError::BusFault { addr: port as usize, message })?;
+                true
+            }
+            None => false,
+        };
+        self.record(port, value, AccessKind::Write, claimed);
+        Ok(())
+    }
+
+    /// Read a port without side effects, for the debugger
+    pub fn peek(&self, port: u8) -> u8 {
+        match self.device_at(port) {
+            Some(index) => {
+                let mapping = &self.mappings[index];
+                mapping.device.peek((port - mapping.ports.start()) as usize)
+            }
+            None => self.unclaimed,
+        }
+    }
+
+    /// Add an access that was handled outside the port bus to the trace
+    pub fn record(&mut self, port: u8, value: u8, kind: AccessKind, claimed: bool) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(PortAccess {
+                cycle: self.cycle,
+                port,
+                value,
+                kind,
+                claimed,
+            });
+        }
+    }
+
+    /// Let the attached devices advance by `cycles` clock cycles
+    pub fn tick(&mut self, cycles: u32) {
+        self.cycle += cycles as u64;
+        for mapping in &mut self.mappings {
+            mapping.device.tick(cycles);
+        }
+    }
+
+    /// Let the attached devices transfer to and from `memory`
+    pub fn dma(&mut self, memory: &mut dyn Bus) {
+        for mapping in &mut self.mappings {
+            mapping.device.dma(memory);
+        }
+    }
+
+    fn device_at(&self, port: u8) -> Option<usize> {
+        self.mappings.iter().position(|mapping| mapping.ports.contains(&port))
+    }
+}
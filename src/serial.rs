@@ -0,0 +1,77 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+
+use crate::terminal;
+
+/// Host end of the emulated serial port
+pub enum SerialBridge {
+    Stdio(Receiver<u8>), // Bytes from stdin, read on a background thread; output goes to stdout
+    Socket(UnixStream),  // A client connected to a Unix domain socket
+}
+
+impl SerialBridge {
+    /// Bridge to the emulator's own stdin and stdout
+    pub fn stdio() -> Self {
+        SerialBridge::Stdio(terminal::spawn_stdin_reader())
+    }
+
+    /// Listen on a Unix domain socket at `path` and wait for one client to connect.
+    ///
+    /// A stale socket file left by an earlier run is replaced. To get a pty
+    /// instead, connect with e.g. `socat PTY,link=/tmp/mbee,raw UNIX-CONNECT:path`.
+    pub fn listen(path: &Path) -> io::Result<Self> {
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        let (stream, _) = listener.accept()?;
+        stream.set_nonblocking(true)?;
+        Ok(SerialBridge::Socket(stream))
+    }
+
+    /// Bytes that have arrived from the host since the last poll, without blocking
+    pub fn poll(&mut self) -> io::Result<Vec<u8>> {
+        match self {
+            SerialBridge::Stdio(input) => Ok(input.try_iter().collect()),
+            SerialBridge::Socket(stream) => {
+                let mut bytes = Vec::new();
+                let mut buffer = [0; 256];
+                loop {
+                    match stream.read(&mut buffer) {
+                        Ok(0) => break, // Client closed the connection
+                        Ok(count) => bytes.extend_from_slice(&buffer[..count]),
+                        Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                        Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                        Err(err) => return Err(err),
+                    }
+                }
+                Ok(bytes)
+            }
+        }
+    }
+
+    /// Send bytes the emulated program transmitted to the host
+    pub fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match self {
+            SerialBridge::Stdio(_) => {
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(bytes)?;
+                stdout.flush()
+            }
+            SerialBridge::Socket(stream) => {
+                let mut rest = bytes;
+                while !rest.is_empty() {
+                    match stream.write(rest) {
+                        Ok(count) => rest = &rest[count..],
+                        Err(err) if err.kind() == ErrorKind::WouldBlock => std::thread::yield_now(),
+                        Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                        Err(err) => return Err(err),
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
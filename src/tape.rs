@@ -0,0 +1,287 @@
+use std::path::Path;
+
+use crate::wav;
+
+/// Signature at the start of a compact MicroBee tape image (.tap)
+pub const TAP_SIGNATURE: &[u8] = b"TAP_DGOS_MBEE";
+
+/// Kansas City Standard tones: a 0 bit is SPACE_HZ, a 1 bit is MARK_HZ
+pub const SPACE_HZ: u32 = 1200;
+pub const MARK_HZ: u32 = 2400;
+
+/// Bits sent per byte: a start bit, eight data bits and two stop bits
+pub const BITS_PER_BYTE: u64 = 11;
+
+/// Seconds of mark tone written before and after the data in a WAV recording
+const LEADER_SECONDS: f64 = 0.5;
+
+/// Peak level of the tones written to WAV files
+const AMPLITUDE: f64 = 12000.0;
+
+/// Sample rate of WAV recordings
+pub const WAV_SAMPLE_RATE: u32 = 22050;
+
+/// Cassette baud rates the MicroBee supports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Baud {
+    B300,
+    B1200,
+}
+
+impl Baud {
+    /// Bits per second
+    pub fn rate(self) -> u32 {
+        match self {
+            Baud::B300 => 300,
+            Baud::B1200 => 1200,
+        }
+    }
+
+    /// Lowest sample rate audio at this baud decodes reliably from: the tones' half
+    /// cycles must be told apart, and at 1200 baud each bit has few of them
+    pub fn min_sample_rate(self) -> u32 {
+        match self {
+            Baud::B300 => 8000,
+            Baud::B1200 => 11025,
+        }
+    }
+
+    /// Parse `300` or `1200`
+    pub fn parse(text: &str) -> Option<Baud> {
+        match text {
+            "300" => Some(Baud::B300),
+            "1200" => Some(Baud::B1200),
+            _ => None,
+        }
+    }
+}
+
+/// Bytes recorded on a tape, in order.
+///
+/// Stored on the host either as a compact image, `TAP_SIGNATURE` followed by
+/// the bytes, or as Kansas City Standard audio in a WAV file: each byte is a
+/// 0 start bit, eight data bits from the least significant, and two 1 stop
+/// bits, with 0s sent as 1200Hz and 1s as 2400Hz for one bit time.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tape {
+    pub bytes: Vec<u8>,
+}
+
+impl Tape {
+    /// Read a compact image; a file without the signature is taken as raw bytes
+    pub fn from_tap(data: &[u8]) -> Tape {
+        let bytes = data.strip_prefix(TAP_SIGNATURE).unwrap_or(data);
+        Tape { bytes: bytes.to_vec() }
+    }
+
+    /// Compact image of the tape
+    pub fn to_tap(&self) -> Vec<u8> {
+        [TAP_SIGNATURE, &self.bytes].concat()
+    }
+
+    /// Load a tape file: WAV audio recorded at `baud` if the name ends in .wav, else a compact image
+    pub fn load(path: &Path, baud: Baud) -> Result<Tape, String> {
+        let data = std::fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        if is_wav(path) {
+            let (sample_rate, samples) = wav::read_pcm(&data).map_err(|err| format!("{}: {}", path.display(), err))?;
+            Tape::decode(&samples, sample_rate, baud).map_err(|err| format!("{}: {}", path.display(), err))
+        } else {
+            Ok(Tape::from_tap(&data))
+        }
+    }
+
+    /// Save the tape as WAV audio at `baud` if the name ends in .wav, else as a compact image
+    pub fn save(&self, path: &Path, baud: Baud) -> Result<(), String> {
+        let data = if is_wav(path) {
+            let mut data = Vec::new();
+            wav::write_pcm16(&mut data, WAV_SAMPLE_RATE, &self.encode(baud, WAV_SAMPLE_RATE))
+                .map_err(|err| format!("{}: {}", path.display(), err))?;
+            data
+        } else {
+            self.to_tap()
+        };
+        std::fs::write(path, data).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    /// Render the tape as Kansas City Standard audio; `decode` needs at least
+    /// `baud.min_sample_rate()` to read it back
+    pub fn encode(&self, baud: Baud, sample_rate: u32) -> Vec<i16> {
+        let mut bits = Vec::with_capacity(self.bytes.len() * BITS_PER_BYTE as usize);
+        for &byte in &self.bytes {
+            bits.push(false);
+            bits.extend((0..8).map(|bit| byte & (1 << bit) != 0));
+            bits.extend([true, true]);
+        }
+        let leader = (LEADER_SECONDS * baud.rate() as f64) as usize;
+        let bits = std::iter::repeat_n(true, leader).chain(bits).chain(std::iter::repeat_n(true, leader));
+
+        // Every bit holds a whole number of cycles of its tone, so each starts at phase 0
+        let samples_per_bit = sample_rate as f64 / baud.rate() as f64;
+        let mut samples = Vec::new();
+        let mut time = 0.0;
+        for bit in bits {
+            let hz = if bit { MARK_HZ } else { SPACE_HZ } as f64;
+            let end = time + samples_per_bit;
+            let first = samples.len();
+            while (samples.len() as f64) < end {
+                let t = (samples.len() - first) as f64 / sample_rate as f64;
+                samples.push(((t * hz * std::f64::consts::TAU).sin() * AMPLITUDE) as i16);
+            }
+            time = end;
+        }
+        samples
+    }
+
+    /// Recover the bytes from Kansas City Standard audio recorded at `baud`.
+    ///
+    /// Each half cycle between zero crossings is classed as mark or space by
+    /// its length, then bytes are framed like a UART: a space after mark
+    /// starts a byte, bits are sampled mid-way, and bytes without a mark
+    /// stop bit are dropped. Fails if `sample_rate` is below `baud.min_sample_rate()`.
+    pub fn decode(samples: &[i16], sample_rate: u32, baud: Baud) -> Result<Tape, String> {
+        if sample_rate < baud.min_sample_rate() {
+            return Err(format!(
+                "{} Hz is too low a sample rate for {} baud; it needs at least {} Hz",
+                sample_rate,
+                baud.rate(),
+                baud.min_sample_rate()
+            ));
+        }
+        // Half cycles as (start time in seconds, is mark), split at crossings of a small hysteresis band
+        let threshold = samples.iter().map(|&sample| (sample as i32).abs()).max().unwrap_or(0) / 8;
+        let boundary = 0.75 / (2 * SPACE_HZ) as f64; // Between the half periods of the two tones
+        let mut halves: Vec<(f64, bool)> = Vec::new();
+        let mut positive = None;
+        let mut last_crossing = None;
+        for (index, &sample) in samples.iter().enumerate() {
+            let sample = sample as i32;
+            let side = if sample > threshold {
+                true
+            } else if sample < -threshold {
+                false
+            } else {
+                continue;
+            };
+            if positive == Some(side) {
+                continue;
+            }
+            positive = Some(side);
+            let time = index as f64 / sample_rate as f64;
+            if let Some(start) = last_crossing {
+                halves.push((start, time - start < boundary));
+            }
+            last_crossing = Some(time);
+        }
+        let mark_at = |time: f64| {
+            let index = halves.partition_point(|&(start, _)| start <= time);
+            index == 0 || halves[index - 1].1
+        };
+
+        let bit_time = 1.0 / baud.rate() as f64;
+        let mut bytes = Vec::new();
+        let mut index = 0;
+        while index < halves.len() {
+            let (start, mark) = halves[index];
+            if mark {
+                index += 1;
+                continue;
+            }
+            let sample = |bit: u32| mark_at(start + (bit as f64 + 0.5) * bit_time);
+            if !sample(0) && sample(9) {
+                bytes.push((1..=8).fold(0, |byte, bit| byte | ((sample(bit) as u8) << (bit - 1))));
+                let next = start + 9.5 * bit_time; // Look for the next start bit from the middle of the stop bit
+                index = halves.partition_point(|&(start, _)| start < next);
+            } else {
+                index += 1;
+            }
+        }
+        Ok(Tape { bytes })
+    }
+}
+
+fn is_wav(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("wav"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every byte value, then a few that stress framing: all 0 and all 1 bits back to back
+    fn test_tape() -> Tape {
+        let mut bytes: Vec<u8> = (0..=255).collect();
+        bytes.extend([0x00, 0x00, 0xFF, 0xFF, 0x55, 0xAA]);
+        Tape { bytes }
+    }
+
+    #[test]
+    fn tap_images_round_trip() {
+        let tape = test_tape();
+        let image = tape.to_tap();
+        assert!(image.starts_with(TAP_SIGNATURE));
+        assert_eq!(Tape::from_tap(&image), tape);
+        assert_eq!(Tape::from_tap(b"raw"), Tape { bytes: b"raw".to_vec() });
+    }
+
+    #[test]
+    fn audio_round_trips_at_every_baud_and_sample_rate() {
+        let tape = test_tape();
+        for baud in [Baud::B300, Baud::B1200] {
+            for sample_rate in [baud.min_sample_rate(), 11025, 16000, WAV_SAMPLE_RATE, 44100, 48000] {
+                let samples = tape.encode(baud, sample_rate);
+                let decoded = Tape::decode(&samples, sample_rate, baud);
+                assert_eq!(decoded, Ok(tape.clone()), "{:?} at {} Hz", baud, sample_rate);
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_quiet_offset_noisy_audio() {
+        let tape = test_tape();
+        for baud in [Baud::B300, Baud::B1200] {
+            let mut seed = 1u32;
+            let samples: Vec<i16> = tape
+                .encode(baud, WAV_SAMPLE_RATE)
+                .iter()
+                .map(|&sample| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                    let noise = (seed >> 16) as i32 % 800 - 400;
+                    (sample as i32 / 4 + 300 + noise / 4) as i16
+                })
+                .collect();
+            assert_eq!(Tape::decode(&samples, WAV_SAMPLE_RATE, baud), Ok(tape.clone()), "{:?}", baud);
+        }
+    }
+
+    #[test]
+    fn decodes_through_a_wav_file() {
+        let tape = test_tape();
+        let mut file = Vec::new();
+        wav::write_pcm16(&mut file, WAV_SAMPLE_RATE, &tape.encode(Baud::B1200, WAV_SAMPLE_RATE)).unwrap();
+        let (sample_rate, samples) = wav::read_pcm(&file).unwrap();
+        assert_eq!(Tape::decode(&samples, sample_rate, Baud::B1200), Ok(tape));
+    }
+
+    #[test]
+    fn silence_and_leader_decode_to_nothing() {
+        assert_eq!(Tape::decode(&[0; 10_000], WAV_SAMPLE_RATE, Baud::B300), Ok(Tape::default()));
+        let leader = Tape::default().encode(Baud::B300, WAV_SAMPLE_RATE);
+        assert!(!leader.is_empty());
+        assert_eq!(Tape::decode(&leader, WAV_SAMPLE_RATE, Baud::B300), Ok(Tape::default()));
+    }
+
+    #[test]
+    fn rejects_sample_rates_too_low_for_the_baud() {
+        let samples = test_tape().encode(Baud::B1200, 8000);
+        assert!(Tape::decode(&samples, 8000, Baud::B1200).is_err());
+        assert!(Tape::decode(&samples, 8000, Baud::B300).is_ok());
+        assert!(Tape::decode(&[], 7999, Baud::B300).is_err());
+    }
+
+    #[test]
+    fn parses_baud_rates() {
+        assert_eq!(Baud::parse("300"), Some(Baud::B300));
+        assert_eq!(Baud::parse("1200"), Some(Baud::B1200));
+        assert_eq!(Baud::parse("2400"), None);
+    }
+}
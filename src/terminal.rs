@@ -0,0 +1,64 @@
+use std::io::{IsTerminal, Read};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+/// Puts the host terminal into unbuffered, no-echo mode until dropped.
+///
+/// Uses `stty`, so it is a no-op when stdin is not a terminal or `stty` is
+/// unavailable. Signals are disabled as well, so Ctrl-C reaches the emulated
+/// program instead of killing the emulator with the terminal left raw.
+pub struct RawMode {
+    saved: Option<String>, // `stty -g` settings to restore
+}
+
+impl RawMode {
+    /// Switch stdin to raw mode if it is a terminal
+    pub fn enable() -> Self {
+        if !std::io::stdin().is_terminal() {
+            return RawMode { saved: None };
+        }
+        let saved = stty(&["-g"]).map(|settings| settings.trim().to_string());
+        if saved.is_some() {
+            stty(&["-icanon", "-echo", "-isig", "min", "1"]);
+        }
+        RawMode { saved }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if let Some(settings) = &self.saved {
+            stty(&[settings.as_str()]);
+        }
+    }
+}
+
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Read stdin byte by byte on a background thread, so the emulation loop can
+/// poll for input without blocking. The channel closes at end of input.
+pub fn spawn_stdin_reader() -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stdin = std::io::stdin().lock();
+        let mut byte = [0];
+        while let Ok(1) = stdin.read(&mut byte) {
+            if sender.send(byte[0]).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
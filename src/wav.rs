@@ -0,0 +1,71 @@
+use std::io::{self, Write};
+
+/// Write mono 16-bit PCM samples as a WAV file
+pub fn write_pcm16(mut out: impl Write, sample_rate: u32, samples: &[i16]) -> io::Result<()> {
+    let data_size = u32::try_from(samples.len() * 2)
+        .ok()
+        .filter(|&size| size <= u32::MAX - 36)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "too many samples for a WAV file"))?;
+    let byte_rate = sample_rate
+        .checked_mul(2)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "sample rate too high for a WAV file"))?;
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_size).to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?; // PCM
+    out.write_all(&1u16.to_le_bytes())?; // Mono
+    out.write_all(&sample_rate.to_le_bytes())?;
+    out.write_all(&byte_rate.to_le_bytes())?; // Bytes per second
+    out.write_all(&2u16.to_le_bytes())?; // Bytes per sample frame
+    out.write_all(&16u16.to_le_bytes())?; // Bits per sample
+    out.write_all(b"data")?;
+    out.write_all(&data_size.to_le_bytes())?;
+    let bytes: Vec<u8> = samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+    out.write_all(&bytes)
+}
+
+/// Read a PCM WAV file as 16-bit samples and its sample rate.
+///
+/// Takes 8- or 16-bit PCM with any number of channels, keeping only the first.
+pub fn read_pcm(bytes: &[u8]) -> Result<(u32, Vec<i16>), String> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("not a WAV file".to_string());
+    }
+    let mut format = None;
+    let mut position = 12;
+    while position + 8 <= bytes.len() {
+        let kind = &bytes[position..position + 4];
+        let size = u32::from_le_bytes(bytes[position + 4..position + 8].try_into().unwrap()) as usize;
+        let body = &bytes[position + 8..(position + 8 + size).min(bytes.len())];
+        match kind {
+            b"fmt " if body.len() >= 16 => {
+                let field = |offset: usize| u16::from_le_bytes([body[offset], body[offset + 1]]);
+                let sample_rate = u32::from_le_bytes(body[4..8].try_into().unwrap());
+                format = Some((field(0), field(2), sample_rate, field(12), field(14)));
+            }
+            b"data" => {
+                let Some((1, channels, sample_rate, block_align, bits)) = format else {
+                    return Err("only PCM WAV files are supported".to_string());
+                };
+                if channels == 0 || sample_rate == 0 || block_align < bits.div_ceil(8) || block_align == 0 {
+                    return Err("bad WAV format".to_string());
+                }
+                let samples = body
+                    .chunks_exact(block_align as usize)
+                    .map(|frame| match bits {
+                        8 => Ok(((frame[0] as i16) - 128) << 8),
+                        16 => Ok(i16::from_le_bytes([frame[0], frame[1]])),
+                        _ => Err(format!("{}-bit samples are not supported", bits)),
+                    })
+                    .collect::<Result<Vec<i16>, String>>()?;
+                return Ok((sample_rate, samples));
+            }
+            _ => {}
+        }
+        position += 8 + size + (size & 1); // Chunks are padded to an even length
+    }
+    Err("WAV file has no data".to_string())
+}